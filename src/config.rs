@@ -0,0 +1,340 @@
+use crate::utils::text::*;
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use std::{fs::File, io::ErrorKind};
+use toml::value::Table;
+use toml::Value;
+
+pub struct Config {
+	pub template_path: String,
+	pub granite_path: String,
+	pub html_path: String,
+	pub plugin_path: String,
+	// toml files in here are given to templates as `data.*`, it doesn't have to exist
+	pub data_path: String,
+	// where plugin output is cached, -> plugins/cache.rs
+	pub cache_path: String,
+	// music_path: String,
+	// latest_length: usize,
+	pub last_run: u64,
+	// everything under [values], handed to templates as `site.*`
+	pub values: Table,
+	// `base_url` from [values], like "https://example.com", for making urls absolute
+	pub base_url: Option<String>,
+	// [templates] pairs of a path glob and the template for pages it matches, like "notes/**" = "note"
+	pub templates: Vec<(String, String)>,
+	// the [plugins] table
+	pub plugins: PluginConfig,
+	// the [dates] table
+	pub dates: DateConfig,
+	// the [feeds] table, if there is one
+	pub feeds: Option<FeedConfig>,
+	// when this run started, handed to templates as `build.time`
+	pub build_time: u64,
+}
+
+/*
+settings for running plugins, from the [plugins] table:
+```toml
+[plugins]
+timeout = 30
+rounds = 10
+allow = ["latest", "files"]
+wasm_memory = 64
+
+[plugins.timeouts]
+latest = 120
+```
+*/
+pub struct PluginConfig {
+	// how many seconds a plugin can run for before it's stopped
+	pub timeout: u64,
+	// the same, for particular plugins
+	pub timeouts: HashMap<String, u64>,
+	// how many times a page is searched for new tags before giving up on it
+	pub rounds: usize,
+	// the only plugins pages can use, if it's set
+	pub allow: Option<Vec<String>>,
+	// how many megabytes of memory a `.wasm` plugin can use
+	pub wasm_memory: usize,
+}
+
+impl PluginConfig {
+	fn new(table: Option<&Table>) -> PluginConfig {
+		let table = match table {
+			Some(t) => t.clone(),
+			None => Table::new(),
+		};
+		let seconds = |v: &Value| v.as_integer().filter(|i| *i > 0).map(|i| i as u64);
+		let timeouts = match table.get("timeouts").and_then(|v| v.as_table()) {
+			Some(t) => t
+				.iter()
+				.filter_map(|(name, v)| Some((name.clone(), seconds(v)?)))
+				.collect(),
+			None => HashMap::new(),
+		};
+		PluginConfig {
+			timeout: table.get("timeout").and_then(seconds).unwrap_or(30),
+			timeouts,
+			rounds: table
+				.get("rounds")
+				.and_then(|v| v.as_integer())
+				.filter(|i| *i > 0)
+				.unwrap_or(10) as usize,
+			allow: table.get("allow").and_then(|v| v.as_array()).map(|a| {
+				a.iter()
+					.filter_map(|v| Some(v.as_str()?.to_string()))
+					.collect()
+			}),
+			wasm_memory: table
+				.get("wasm_memory")
+				.and_then(|v| v.as_integer())
+				.filter(|i| *i > 0)
+				.unwrap_or(64) as usize,
+		}
+	}
+
+	// a timeout set here for the plugin wins over one in its plugin.toml, which wins over the default
+	pub fn timeout(&self, plugin: &str, manifest: Option<u64>) -> Duration {
+		let seconds = self.timeouts.get(plugin).copied();
+		Duration::from_secs(seconds.or(manifest).unwrap_or(self.timeout))
+	}
+}
+
+/*
+how `{{date}}` is written, from the [dates] table:
+```toml
+[dates]
+format = "%e %B %Y"
+utc_offset = "+02:00"
+source = ["meta", "git", "modified"]
+
+[dates.formats]
+short = "%d%m%y"
+```
+these are checked when they're used, -> dates.rs
+*/
+pub struct DateConfig {
+	// the format `{{date}}` uses when it isn't given one
+	pub format: String,
+	// a utc offset like "+02:00", or "UTC", which is the same all year
+	pub utc_offset: String,
+	// where a page's date comes from, the first one that has a date is used
+	pub sources: Vec<String>,
+	// formats with names, so `{{date short}}` is the same as `{{date "%d%m%y"}}`
+	pub formats: HashMap<String, String>,
+}
+
+impl DateConfig {
+	fn new(table: Option<&Table>) -> DateConfig {
+		let table = match table {
+			Some(t) => t.clone(),
+			None => Table::new(),
+		};
+		let text = |key: &str, default: &str| match table.get(key).and_then(|v| v.as_str()) {
+			Some(t) => t.to_string(),
+			None => default.to_string(),
+		};
+		let sources = match table.get("source") {
+			Some(Value::String(s)) => vec![s.clone()],
+			Some(Value::Array(a)) => a
+				.iter()
+				.filter_map(|v| Some(v.as_str()?.to_string()))
+				.collect(),
+			_ => vec![String::from("modified")],
+		};
+		let formats = match table.get("formats").and_then(|v| v.as_table()) {
+			Some(t) => t
+				.iter()
+				.filter_map(|(name, v)| Some((name.clone(), v.as_str()?.to_string())))
+				.collect(),
+			None => HashMap::new(),
+		};
+		DateConfig {
+			format: text("format", "%d%m%y"),
+			utc_offset: text("utc_offset", "UTC"),
+			sources,
+			formats,
+		}
+	}
+}
+
+/*
+atom and rss feeds of the site, from the [feeds] table, which feeds are only written with:
+```toml
+[feeds]
+title = "Mineral Existence"
+description = "notes and things"
+author = "benh"
+email = "benh@example.com"
+content = "excerpt"
+length = 20
+formats = ["atom", "rss"]
+sections = ["notes"]
+```
+they also need `base_url` in [values], -> feeds.rs
+*/
+pub struct FeedConfig {
+	pub title: Option<String>,
+	// the atom subtitle and rss description, which is the title if it isn't set
+	pub description: Option<String>,
+	pub author: Option<String>,
+	pub email: Option<String>,
+	// whether entries have an `excerpt` of the page or its `full` content
+	pub content: String,
+	// how many pages each feed has, or every page if it isn't set
+	pub length: Option<usize>,
+	// "atom" for feed.xml and "rss" for rss.xml
+	pub formats: Vec<String>,
+	// directories that get their own feeds too, like `notes/feed.xml`
+	pub sections: Vec<String>,
+}
+
+impl FeedConfig {
+	fn new(table: &Table) -> FeedConfig {
+		let text = |key: &str| {
+			table
+				.get(key)
+				.and_then(|v| v.as_str())
+				.map(|t| t.to_string())
+		};
+		let list = |key: &str| {
+			table.get(key).and_then(|v| v.as_array()).map(|a| {
+				a.iter()
+					.filter_map(|v| Some(v.as_str()?.to_string()))
+					.collect::<Vec<_>>()
+			})
+		};
+		FeedConfig {
+			title: text("title"),
+			description: text("description"),
+			author: text("author"),
+			email: text("email"),
+			content: text("content").unwrap_or_else(|| String::from("excerpt")),
+			length: table
+				.get("length")
+				.and_then(|v| v.as_integer())
+				.filter(|i| *i > 0)
+				.map(|i| i as usize),
+			formats: list("formats")
+				.unwrap_or_else(|| vec![String::from("atom"), String::from("rss")]),
+			sections: list("sections").unwrap_or_default(),
+		}
+	}
+}
+
+impl Config {
+	pub fn new() -> Option<Config> {
+		File::open(".pillar.toml").unwrap_or_else(|error| {
+			if error.kind() == ErrorKind::NotFound {
+				File::create(".pillar.toml").unwrap_or_else(|create_error| {
+					panic!("Problem creating the file: {:?}", create_error);
+				});
+				let default = "[paths]\n\
+	                template_path = \"templates/\"\n\
+	                granite_path = \"pages/\"\n\
+	                html_path = \"docs/\"\n\
+	                plugin_path = \"plugins/\"\n\
+	                music_path = \"/home/user/Music/\"\n\
+	                \n\
+	                [values]\n\
+	                latest_length = 15\n\
+	                last_run = 0";
+				fs::write(".pillar.toml", default).unwrap();
+				File::open(".pillar.toml").unwrap()
+			} else {
+				panic!("Problem opening the file: {:?}", error);
+			}
+		});
+
+		let config_string = fs::read_to_string(".pillar.toml").unwrap();
+		let config = config_string.parse::<Value>().unwrap();
+
+		let template_path = config["paths"]["template_path"].to_string();
+		let granite_path = config["paths"]["granite_path"].to_string();
+		let html_path = config["paths"]["html_path"].to_string();
+		let plugin_path = config["paths"]["plugin_path"].to_string();
+		let data_path = match config["paths"].get("data_path").and_then(|v| v.as_str()) {
+			Some(p) => p.to_string(),
+			None => String::from("data/"),
+		};
+		let cache_path = match config["paths"].get("cache_path").and_then(|v| v.as_str()) {
+			Some(p) => p.to_string(),
+			None => String::from(".pillar-cache/"),
+		};
+		// let music_path = config["paths"]["music_path"].to_string();
+		// let latest_length = config["values"]["latest_length"]
+		// .to_string()
+		// .parse::<usize>()
+		// .unwrap();
+		let last_run = config["values"]["last_run"]
+			.to_string()
+			.parse::<u64>()
+			.unwrap();
+		let values = match config.get("values").and_then(|v| v.as_table()) {
+			Some(t) => t.clone(),
+			None => Table::new(),
+		};
+		let base_url = values
+			.get("base_url")
+			.and_then(|v| v.as_str())
+			.map(|b| b.to_string());
+		let templates = match config.get("templates").and_then(|v| v.as_table()) {
+			Some(t) => t
+				.iter()
+				.filter_map(|(glob, name)| Some((glob.clone(), name.as_str()?.to_string())))
+				.collect(),
+			None => Vec::new(),
+		};
+		let plugins = PluginConfig::new(config.get("plugins").and_then(|v| v.as_table()));
+		let dates = DateConfig::new(config.get("dates").and_then(|v| v.as_table()));
+		let feeds = config
+			.get("feeds")
+			.and_then(|v| v.as_table())
+			.map(FeedConfig::new);
+
+		Some(Config {
+			template_path: slice(&template_path, 1..len(&template_path) - 1),
+			granite_path: slice(&granite_path, 1..len(&granite_path) - 1),
+			html_path: slice(&html_path, 1..len(&html_path) - 1),
+			plugin_path: slice(&plugin_path, 1..len(&plugin_path) - 1),
+			data_path,
+			cache_path,
+			// music_path: slice(&music_path, 1..len(&music_path)-1),
+			// latest_length,
+			last_run,
+			values,
+			base_url,
+			templates,
+			plugins,
+			dates,
+			feeds,
+			build_time: now(),
+		})
+	}
+
+	pub fn update_time() {
+		let config_str = &fs::read_to_string(".pillar.toml").unwrap();
+		let now = now();
+
+		let mut config_string = String::new();
+		for line in config_str.lines() {
+			if slice(line, 0..8) == "last_run" {
+				config_string.push_str(&format!("last_run = {}\n", now));
+			} else {
+				config_string.push_str(&format!("{}\n", line));
+			}
+		}
+
+		fs::write(".pillar.toml", config_string).unwrap();
+	}
+}
+
+// seconds since the unix epoch
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap()
+		.as_secs()
+}
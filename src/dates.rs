@@ -0,0 +1,287 @@
+use crate::config::DateConfig;
+use crate::pipeline::{PageCtx, Stage, Transform};
+use crate::plugins::{self, Tag};
+use crate::site::Source;
+use crate::utils::date;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::process::Command;
+
+/*
+where a page's date comes from:
+its `date` meta variable, when its granite was last modified or created, or when it was last committed to git
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateSource {
+	Meta,
+	Modified,
+	Created,
+	Git,
+}
+
+impl DateSource {
+	const ALL: [DateSource; 4] = [
+		DateSource::Meta,
+		DateSource::Modified,
+		DateSource::Created,
+		DateSource::Git,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			DateSource::Meta => "meta",
+			DateSource::Modified => "modified",
+			DateSource::Created => "created",
+			DateSource::Git => "git",
+		}
+	}
+
+	fn parse(name: &str) -> Result<DateSource, String> {
+		DateSource::ALL
+			.iter()
+			.find(|s| s.name() == name)
+			.copied()
+			.ok_or_else(|| {
+				format!(
+					"`{}` isn't a date source, it should be meta, modified, created or git",
+					name
+				)
+			})
+	}
+}
+
+/*
+replaces `{{date}}` in built pages, without a plugin:
+```
+{{date}}                  the page's date in the [dates] format
+{{date short}}            in a format named in [dates.formats]
+{{date "%A %e %B %Y"}}    in any strftime style format, -> utils.rs:[date::format(secs: i64, fmt: &str)]
+{{date source: git}}      from somewhere other than the [dates] sources
+```
+a post-template plugin that handles `date` replaces it instead
+*/
+pub struct Dates {
+	format: String,
+	formats: HashMap<String, String>,
+	// seconds east of utc
+	offset: i64,
+	sources: Vec<DateSource>,
+	// when each file was last committed, read from git the first time a page needs it
+	git: RefCell<Option<HashMap<String, i64>>>,
+}
+
+impl Dates {
+	pub fn new(config: &DateConfig) -> Result<Dates, String> {
+		// named timezones like "Europe/Berlin" aren't supported, since they'd need the daylight saving rules for every zone
+		let offset = date::parse_offset(&config.utc_offset).ok_or_else(|| {
+			format!(
+				"`utc_offset` is `{}`, but it should be a fixed offset like \"+02:00\" or \"UTC\", not a timezone's name",
+				config.utc_offset
+			)
+		})?;
+		let sources = config
+			.sources
+			.iter()
+			.map(|s| DateSource::parse(s))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(Dates {
+			format: config.format.clone(),
+			formats: config.formats.clone(),
+			offset,
+			sources,
+			git: RefCell::new(None),
+		})
+	}
+
+	/*
+	replaces every `{{date}}` tag in a page
+	*/
+	pub fn replace(&self, source: &Source, content: &str) -> Result<String, String> {
+		let mut output = content.to_string();
+		for raw in plugins::candidates(content) {
+			let tag = Tag::parse(raw)?;
+			if tag.name != "date" {
+				continue;
+			}
+			let mut format = None;
+			let mut sources = None;
+			for arg in &tag.args {
+				format = Some(self.lookup(arg)?);
+			}
+			for (name, value) in &tag.named {
+				match name.as_str() {
+					"format" => format = Some(self.lookup(value)?),
+					"source" => sources = Some(vec![DateSource::parse(value)?]),
+					_ => return Err(format!("`{}` doesn't take `{}`", raw, name)),
+				}
+			}
+			let secs = self.date(source, sources.as_deref().unwrap_or(&self.sources))?;
+			let format = format.unwrap_or(&self.format);
+			output = output.replace(raw, &date::format(secs, format));
+		}
+		Ok(output)
+	}
+
+	// a page's date from the [dates] sources, -> Dates::date
+	pub fn of(&self, source: &Source) -> Result<i64, String> {
+		self.date(source, &self.sources)
+	}
+
+	// a date from `of` in the [dates] format
+	pub fn format(&self, secs: i64) -> String {
+		date::format(secs, &self.format)
+	}
+
+	// seconds east of utc that dates from `of` are in
+	pub fn offset(&self) -> i64 {
+		self.offset
+	}
+
+	// a format with a name, or one written out, which has at least one `%` in it
+	fn lookup<'a>(&'a self, format: &'a str) -> Result<&'a str, String> {
+		if format.contains('%') {
+			return Ok(format);
+		}
+		match self.formats.get(format) {
+			Some(f) => Ok(f),
+			None => Err(format!(
+				"there's no date format named `{}` in [dates.formats]",
+				format
+			)),
+		}
+	}
+
+	/*
+	the page's date from the first source that has one, as seconds since the epoch at the [dates] utc offset
+	*/
+	fn date(&self, source: &Source, sources: &[DateSource]) -> Result<i64, String> {
+		for from in sources {
+			let utc = match from {
+				DateSource::Meta => match source.meta("date") {
+					// a date without an offset is already at the site's utc offset
+					Some(d) if !date::has_offset(d) => {
+						return date::parse(d)
+							.ok_or_else(|| format!("its `date`, `{}`, isn't a date", d))
+					}
+					Some(d) => date::parse(d)
+						.map(Some)
+						.ok_or_else(|| format!("its `date`, `{}`, isn't a date", d))?,
+					None => None,
+				},
+				DateSource::Modified => Some(source.modified as i64),
+				DateSource::Created => Some(source.created as i64),
+				DateSource::Git => self.committed(&source.path),
+			};
+			if let Some(utc) = utc {
+				return Ok(utc + self.offset);
+			}
+		}
+		let sources = sources.iter().map(|s| s.name()).collect::<Vec<_>>();
+		Err(format!(
+			"it doesn't have a date from {}",
+			sources.join(" or ")
+		))
+	}
+
+	fn committed(&self, path: &str) -> Option<i64> {
+		let mut git = self.git.borrow_mut();
+		let dates = git.get_or_insert_with(git_dates);
+		dates.get(path.trim_start_matches("./")).copied()
+	}
+}
+
+// the pipeline borrows the dates, since `{{latest}}` needs them too
+impl Transform for &Dates {
+	fn name(&self) -> String {
+		String::from("dates")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::PostTemplate
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		page.page.content = self.replace(page.source, &page.page.content)?;
+		Ok(())
+	}
+}
+
+/*
+when every file was last committed, from one `git log` for the whole build,
+or nothing if the site isn't in a git repository
+*/
+fn git_dates() -> HashMap<String, i64> {
+	let output = Command::new("git")
+		.args(["-c", "core.quotePath=false", "log", "--format=%x00%ct"])
+		.args(["--name-only", "--relative", "--", "."])
+		.output();
+	match output {
+		Ok(o) if o.status.success() => committed(&String::from_utf8_lossy(&o.stdout)),
+		_ => HashMap::new(),
+	}
+}
+
+// reads `git log` output, newest commit first, so the first time a file shows up is when it was last changed
+fn committed(log: &str) -> HashMap<String, i64> {
+	let mut dates = HashMap::new();
+	for commit in log.split('\0').skip(1) {
+		let mut lines = commit.lines();
+		let time = match lines.next().and_then(|t| t.trim().parse::<i64>().ok()) {
+			Some(t) => t,
+			None => continue,
+		};
+		for file in lines.filter(|l| !l.is_empty()) {
+			dates.entry(file.to_string()).or_insert(time);
+		}
+	}
+	dates
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_dates() {
+		let mut formats = HashMap::new();
+		formats.insert(String::from("long"), String::from("%A %e %B %Y, %H:%M"));
+		let config = DateConfig {
+			format: String::from("%d%m%y"),
+			utc_offset: String::from("+02:00"),
+			sources: vec![String::from("meta"), String::from("modified")],
+			formats,
+		};
+		let dates = Dates::new(&config).unwrap();
+		let mut source = Source {
+			path: String::from("pages/test.gn"),
+			target: String::from("docs/test.html"),
+			url: String::from("test.html"),
+			meta: Vec::new(),
+			// 2023-05-09 23:30 utc, which is the 10th two hours east
+			modified: 1683675000,
+			created: 0,
+			permalink: String::from("test.html"),
+		};
+		let page = "{{date}} | {{date long}} | {{date \"%Y\" source: created}}";
+		assert_eq!(
+			"100523 | Wednesday 10 May 2023, 01:30 | 1970",
+			dates.replace(&source, page).unwrap()
+		);
+
+		source.meta = crate::granite::parse_header("!meta!\ndate: 2023-05-09\n!meta!").meta;
+		assert_eq!("090523", dates.replace(&source, "{{date}}").unwrap());
+		assert!(dates.replace(&source, "{{date short}}").is_err());
+		assert!(dates.replace(&source, "{{date source: git}}").is_err());
+		assert!(dates.replace(&source, "{{date when: now}}").is_err());
+
+		let log =
+			"\x001683675000\n\npages/a.gn\npages/b.gn\n\x001683000000\n\npages/a.gn\npages/c.gn\n";
+		let committed = committed(log);
+		assert_eq!(Some(&1683675000), committed.get("pages/a.gn"));
+		assert_eq!(Some(&1683000000), committed.get("pages/c.gn"));
+
+		let mut config = config;
+		config.utc_offset = String::from("Europe/Paris");
+		assert!(Dates::new(&config).is_err());
+	}
+}
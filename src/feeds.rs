@@ -0,0 +1,625 @@
+use crate::config::{Config, FeedConfig};
+use crate::dates::Dates;
+use crate::filters::escape;
+use crate::site::{self, Source};
+use crate::utils::date;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/*
+writes atom and rss feeds of the site's pages once they're built, from the [feeds] table:
+```
+feed.xml          atom, of every page
+rss.xml           rss 2.0, of every page
+notes/feed.xml    the same for each directory in `sections`, with only the pages in it
+```
+pages are newest first by their date from [dates], and a page with `feed: false` in its header is left out,
+as are pages that can't be dated, with a warning
+*/
+pub struct Feeds<'a> {
+	config: &'a FeedConfig,
+	dates: &'a Dates,
+	html_path: &'a str,
+	base_url: &'a str,
+	title: &'a str,
+	// the newest a feed can be when it has no pages, in utc
+	build_time: i64,
+	full: bool,
+	formats: Vec<Format>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+	Atom,
+	Rss,
+}
+
+impl Format {
+	fn parse(name: &str) -> Result<Format, String> {
+		match name {
+			"atom" => Ok(Format::Atom),
+			"rss" => Ok(Format::Rss),
+			_ => Err(format!(
+				"`{}` isn't a feed format, it should be atom or rss",
+				name
+			)),
+		}
+	}
+
+	fn file(&self) -> &'static str {
+		match self {
+			Format::Atom => "feed.xml",
+			Format::Rss => "rss.xml",
+		}
+	}
+}
+
+// one page in a feed
+struct Entry {
+	title: String,
+	// the page's full url
+	link: String,
+	// its `id`, if it has one, or its full url, which stays the same as long as the page doesn't move
+	id: String,
+	// seconds since the epoch at the [dates] utc offset
+	date: i64,
+	author: Option<String>,
+	// html, either a few sentences or the whole page
+	content: String,
+}
+
+impl<'a> Feeds<'a> {
+	/*
+	nothing if there isn't a [feeds] table
+	*/
+	pub fn new(config: &'a Config, dates: &'a Dates) -> Result<Option<Feeds<'a>>, String> {
+		let feeds = match &config.feeds {
+			Some(f) => f,
+			None => return Ok(None),
+		};
+		let base_url = config.base_url.as_deref().ok_or_else(|| {
+			String::from("feeds need a `base_url` in [values], since everything in them has to be a full url")
+		})?;
+		let title = feeds
+			.title
+			.as_deref()
+			.ok_or_else(|| String::from("feeds need a `title`"))?;
+		let full = match feeds.content.as_str() {
+			"excerpt" => false,
+			"full" => true,
+			other => {
+				return Err(format!(
+					"`content` is `{}`, but it should be excerpt or full",
+					other
+				))
+			}
+		};
+		let formats = feeds
+			.formats
+			.iter()
+			.map(|f| Format::parse(f))
+			.collect::<Result<Vec<_>, _>>()?;
+		if formats.contains(&Format::Atom) && feeds.author.is_none() {
+			return Err(String::from("atom feeds need an `author`"));
+		}
+		Ok(Some(Feeds {
+			config: feeds,
+			dates,
+			html_path: &config.html_path,
+			base_url: base_url.trim_end_matches('/'),
+			title,
+			build_time: config.build_time as i64,
+			full,
+			formats,
+		}))
+	}
+
+	// every feed, relative to the html directory, like `notes/feed.xml`
+	pub fn files(&self) -> Vec<String> {
+		self.sections()
+			.iter()
+			.flat_map(|section| {
+				self.formats
+					.iter()
+					.map(move |f| [section, f.file()].concat())
+			})
+			.collect()
+	}
+
+	/*
+	writes every feed into the html directory, from the pages that have been built, now or in an earlier build
+	*/
+	pub fn write(&self, sources: &[Source]) -> Result<(), String> {
+		let dated = self.dated(sources);
+		for section in self.sections() {
+			let entries = self.entries(&dated, &section)?;
+			for format in &self.formats {
+				let xml = match format {
+					Format::Atom => self.atom(&section, &entries),
+					Format::Rss => self.rss(&section, &entries),
+				};
+				let target = Path::new(self.html_path).join(&section).join(format.file());
+				if let Some(dir) = target.parent() {
+					fs::create_dir_all(dir)
+						.map_err(|e| format!("couldn't make {}: {}", dir.display(), e))?;
+				}
+				fs::write(&target, xml)
+					.map_err(|e| format!("couldn't write {}: {}", target.display(), e))?;
+				println!("+ {}", target.display());
+			}
+		}
+		Ok(())
+	}
+
+	// "" for the whole site, and then each section like "notes/"
+	fn sections(&self) -> Vec<String> {
+		let mut sections = vec![String::new()];
+		for section in &self.config.sections {
+			sections.push(format!("{}/", section.trim_matches('/')));
+		}
+		sections
+	}
+
+	/*
+	the pages that can be in a feed with their dates
+	a page that can't be dated is left out of every feed rather than stopping the build, and is warned about once
+	*/
+	fn dated<'s>(&self, sources: &'s [Source]) -> Vec<(&'s Source, i64)> {
+		let mut dated = Vec::new();
+		for source in sources {
+			if source.meta("feed") == Some("false") {
+				continue;
+			}
+			match self.dates.of(source) {
+				Ok(date) => dated.push((source, date)),
+				Err(e) => println!(
+					"warning: {} is left out of the feeds, since it couldn't be dated: {}",
+					source.path, e
+				),
+			}
+		}
+		dated
+	}
+
+	fn entries(&self, dated: &[(&Source, i64)], section: &str) -> Result<Vec<Entry>, String> {
+		let mut entries = Vec::new();
+		for &(source, date) in dated {
+			if !source.url.starts_with(section) {
+				continue;
+			}
+			// pages that have never been built aren't on the site yet
+			let html = match fs::read_to_string(&source.target) {
+				Ok(h) => h,
+				Err(_) => continue,
+			};
+			let link = site::absolute(Some(self.base_url), &source.url);
+			let id = match source.meta("id") {
+				Some(id) if id.contains(':') => id.to_string(),
+				Some(id) => {
+					return Err(format!(
+						"{} has the id `{}`, which should be a url or a urn like `urn:uuid:...`",
+						source.path, id
+					))
+				}
+				None => link.clone(),
+			};
+			let body = site::absolute_links(page(&html), &source.url, self.base_url);
+			let content = match source.meta("description") {
+				Some(d) if !self.full => escape(d),
+				_ if !self.full => excerpt(&body),
+				_ => body.trim().to_string(),
+			};
+			entries.push(Entry {
+				title: source.meta("title").unwrap_or(&source.url).to_string(),
+				link,
+				id,
+				date,
+				author: source.meta("author").map(|a| a.to_string()),
+				content,
+			});
+		}
+		entries.sort_by_key(|e| -e.date);
+		entries.truncate(self.config.length.unwrap_or(entries.len()));
+		Ok(entries)
+	}
+
+	fn title(&self, section: &str) -> String {
+		match section.trim_end_matches('/') {
+			"" => self.title.to_string(),
+			section => format!("{}: {}", self.title, section),
+		}
+	}
+
+	// when the newest page in a feed is from, or when the site was built if it doesn't have any
+	fn updated(&self, entries: &[Entry]) -> i64 {
+		match entries.first() {
+			Some(e) => e.date,
+			None => self.build_time + self.dates.offset(),
+		}
+	}
+
+	/*
+	an atom feed, -> RFC 4287
+	*/
+	fn atom(&self, section: &str, entries: &[Entry]) -> String {
+		let home = format!("{}/{}", self.base_url, section);
+		let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+		xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+		xml.push_str(&format!(
+			"\t<title>{}</title>\n",
+			escape(&self.title(section))
+		));
+		if let Some(description) = &self.config.description {
+			xml.push_str(&format!("\t<subtitle>{}</subtitle>\n", escape(description)));
+		}
+		xml.push_str(&format!(
+			"\t<link href=\"{}{}\" rel=\"self\" type=\"application/atom+xml\"/>\n",
+			escape(&home),
+			Format::Atom.file()
+		));
+		xml.push_str(&format!("\t<link href=\"{}\"/>\n", escape(&home)));
+		xml.push_str(&format!("\t<id>{}</id>\n", escape(&home)));
+		xml.push_str(&format!(
+			"\t<updated>{}</updated>\n",
+			self.rfc3339(self.updated(entries))
+		));
+		// `new` makes sure atom feeds have an author
+		let author = self.config.author.as_deref().unwrap_or_default();
+		xml.push_str(&format!(
+			"\t<author>\n\t\t<name>{}</name>\n",
+			escape(author)
+		));
+		if let Some(email) = &self.config.email {
+			xml.push_str(&format!("\t\t<email>{}</email>\n", escape(email)));
+		}
+		xml.push_str("\t</author>\n");
+		xml.push_str("\t<generator>Pillar</generator>\n");
+		for entry in entries {
+			xml.push_str("\t<entry>\n");
+			xml.push_str(&format!("\t\t<title>{}</title>\n", escape(&entry.title)));
+			xml.push_str(&format!("\t\t<link href=\"{}\"/>\n", escape(&entry.link)));
+			xml.push_str(&format!("\t\t<id>{}</id>\n", escape(&entry.id)));
+			xml.push_str(&format!(
+				"\t\t<updated>{}</updated>\n",
+				self.rfc3339(entry.date)
+			));
+			if let Some(author) = &entry.author {
+				xml.push_str(&format!(
+					"\t\t<author>\n\t\t\t<name>{}</name>\n\t\t</author>\n",
+					escape(author)
+				));
+			}
+			let element = if self.full { "content" } else { "summary" };
+			xml.push_str(&format!(
+				"\t\t<{0} type=\"html\">{1}</{0}>\n",
+				element,
+				escape(&entry.content)
+			));
+			xml.push_str("\t</entry>\n");
+		}
+		xml.push_str("</feed>\n");
+		xml
+	}
+
+	/*
+	an rss 2.0 feed, with an atom link back to itself like the w3c validator wants
+	*/
+	fn rss(&self, section: &str, entries: &[Entry]) -> String {
+		let home = format!("{}/{}", self.base_url, section);
+		let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+		xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+		xml.push_str("\t<channel>\n");
+		xml.push_str(&format!(
+			"\t\t<title>{}</title>\n",
+			escape(&self.title(section))
+		));
+		xml.push_str(&format!("\t\t<link>{}</link>\n", escape(&home)));
+		let description = self.config.description.as_deref().unwrap_or(self.title);
+		xml.push_str(&format!(
+			"\t\t<description>{}</description>\n",
+			escape(description)
+		));
+		xml.push_str(&format!(
+			"\t\t<atom:link href=\"{}{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+			escape(&home),
+			Format::Rss.file()
+		));
+		xml.push_str(&format!(
+			"\t\t<lastBuildDate>{}</lastBuildDate>\n",
+			self.rfc822(self.updated(entries))
+		));
+		// rss only has room for an author with an email address
+		if let Some(email) = &self.config.email {
+			let editor = match &self.config.author {
+				Some(author) => format!("{} ({})", email, author),
+				None => email.clone(),
+			};
+			xml.push_str(&format!(
+				"\t\t<managingEditor>{}</managingEditor>\n",
+				escape(&editor)
+			));
+		}
+		xml.push_str("\t\t<generator>Pillar</generator>\n");
+		for entry in entries {
+			xml.push_str("\t\t<item>\n");
+			xml.push_str(&format!("\t\t\t<title>{}</title>\n", escape(&entry.title)));
+			xml.push_str(&format!("\t\t\t<link>{}</link>\n", escape(&entry.link)));
+			xml.push_str(&format!(
+				"\t\t\t<guid isPermaLink=\"{}\">{}</guid>\n",
+				entry.id == entry.link,
+				escape(&entry.id)
+			));
+			xml.push_str(&format!(
+				"\t\t\t<pubDate>{}</pubDate>\n",
+				self.rfc822(entry.date)
+			));
+			xml.push_str(&format!(
+				"\t\t\t<description>{}</description>\n",
+				escape(&entry.content)
+			));
+			xml.push_str("\t\t</item>\n");
+		}
+		xml.push_str("\t</channel>\n</rss>\n");
+		xml
+	}
+
+	// like `2023-05-09T14:30:00+02:00`
+	fn rfc3339(&self, secs: i64) -> String {
+		let offset = match self.dates.offset() {
+			0 => String::from("Z"),
+			o => offset(o, ":"),
+		};
+		[date::format(secs, "%Y-%m-%dT%H:%M:%S"), offset].concat()
+	}
+
+	// like `Tue, 09 May 2023 14:30:00 +0200`
+	fn rfc822(&self, secs: i64) -> String {
+		[
+			date::format(secs, "%a, %d %b %Y %H:%M:%S "),
+			offset(self.dates.offset(), ""),
+		]
+		.concat()
+	}
+}
+
+// seconds east of utc as `+02:00`, with `separator` between the hours and minutes
+fn offset(secs: i64, separator: &str) -> String {
+	let sign = if secs < 0 { '-' } else { '+' };
+	let minutes = secs.abs() / 60;
+	format!(
+		"{}{:02}{}{:02}",
+		sign,
+		minutes / 60,
+		separator,
+		minutes % 60
+	)
+}
+
+/*
+the part of a built page that's the page itself, without the template's nav and footer around it,
+which is its `<article>`, its `<main>`, or its `<body>`, whichever it has first
+*/
+fn page(html: &str) -> &str {
+	["article", "main", "body"]
+		.iter()
+		.find_map(|tag| inside(html, tag))
+		.unwrap_or(html)
+}
+
+// what's inside the first of an element
+fn inside<'h>(html: &'h str, tag: &str) -> Option<&'h str> {
+	let open = format!("<{}", tag);
+	let mut from = 0;
+	let at = loop {
+		let at = from + html[from..].find(&open)?;
+		// so `<b` isn't `<body`
+		match html[at + open.len()..].chars().next() {
+			Some('>') | Some(' ') | Some('\t') | Some('\n') => break at,
+			_ => from = at + open.len(),
+		}
+	};
+	let start = at + html[at..].find('>')? + 1;
+	let end = start + html[start..].find(&format!("</{}>", tag))?;
+	Some(&html[start..end])
+}
+
+/*
+the first few sentences of a page, as text without any of its tags, for feeds without the whole page
+*/
+fn excerpt(html: &str) -> String {
+	const WORDS: usize = 50;
+	lazy_static! {
+		static ref TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+	}
+	let text = TAG.replace_all(html, " ");
+	let words = text.split_whitespace().collect::<Vec<_>>();
+	if words.len() > WORDS {
+		format!("{}…", words[..WORDS].join(" "))
+	} else {
+		words.join(" ")
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::config::DateConfig;
+	use crate::granite::parse_header;
+	use std::collections::HashMap;
+
+	/*
+	checks that some xml is well formed, and gives the path to every element in it, like `feed/entry/id`
+	*/
+	fn elements(xml: &str) -> Vec<String> {
+		let tag = Regex::new(r#"<(/?)([a-zA-Z:]+)(?:\s+[a-zA-Z:]+="[^"<]*")*\s*(/?)>"#).unwrap();
+		let body = xml
+			.strip_prefix("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n")
+			.expect("no xml declaration");
+		let mut open = Vec::<&str>::new();
+		let mut paths = Vec::new();
+		let mut last = 0;
+		for caps in tag.captures_iter(body) {
+			let whole = caps.get(0).unwrap();
+			// text between tags can't have any markup left in it
+			assert!(!body[last..whole.start()].contains(['<', '>']));
+			last = whole.end();
+			let name = caps.get(2).unwrap().as_str();
+			if &caps[1] == "/" {
+				assert_eq!(Some(name), open.pop(), "unbalanced </{}>", name);
+				continue;
+			}
+			open.push(name);
+			paths.push(open.join("/"));
+			if &caps[3] == "/" {
+				open.pop();
+			}
+		}
+		assert!(open.is_empty() && body[last..].trim().is_empty());
+		paths
+	}
+
+	fn count(paths: &[String], path: &str) -> usize {
+		paths.iter().filter(|p| *p == path).count()
+	}
+
+	#[test]
+	fn test_feeds() {
+		let dir = std::env::temp_dir().join(format!("pillar-feeds-{}", std::process::id()));
+		fs::create_dir_all(dir.join("notes")).unwrap();
+		let source = |url: &str, header: &str, modified: u64, html: &str| {
+			let target = dir.join(url);
+			fs::write(&target, html).unwrap();
+			Source {
+				path: format!("pages/{}", url.replace(".html", ".gn")),
+				target: target.display().to_string(),
+				url: url.to_string(),
+				meta: parse_header(&format!("!meta!\n{}\n!meta!", header)).meta,
+				modified,
+				created: 0,
+				permalink: url.to_string(),
+			}
+		};
+		let sources = vec![
+			source(
+				"index.html",
+				"title: home\nfeed: false",
+				30,
+				"<body>home</body>",
+			),
+			source(
+				"notes/uxn.html",
+				"title: uxn & varvara\nid: urn:uuid:1234",
+				20,
+				"<nav>x</nav><article class=\"inner\"><p>a <a href=\"../index.html\">vm</a></p></article>",
+			),
+			source(
+				"notes/orca.html",
+				"title: orca\ndescription: a <livecoding> language",
+				10,
+				"<body><p>orca</p></body>",
+			),
+			source(
+				"notes/draft.html",
+				"title: draft\ndate: someday",
+				40,
+				"<body><p>draft</p></body>",
+			),
+		];
+		let dates = Dates::new(&DateConfig {
+			format: String::from("%s"),
+			utc_offset: String::from("+02:00"),
+			sources: vec![String::from("meta"), String::from("modified")],
+			formats: HashMap::new(),
+		})
+		.unwrap();
+		let config = FeedConfig {
+			title: Some(String::from("Mineral Existence")),
+			description: None,
+			author: Some(String::from("benh")),
+			email: Some(String::from("benh@example.com")),
+			content: String::from("full"),
+			length: None,
+			formats: vec![String::from("atom"), String::from("rss")],
+			sections: vec![String::from("notes")],
+		};
+		let mut feeds = Feeds {
+			config: &config,
+			dates: &dates,
+			html_path: "docs",
+			base_url: "https://example.com",
+			title: "Mineral Existence",
+			build_time: 0,
+			full: true,
+			formats: vec![Format::Atom, Format::Rss],
+		};
+		assert_eq!(
+			vec!["feed.xml", "rss.xml", "notes/feed.xml", "notes/rss.xml"],
+			feeds.files()
+		);
+
+		// the draft's date isn't one, so it's left out instead of stopping the build
+		let dated = feeds.dated(&sources);
+		assert_eq!(2, dated.len());
+		let entries = feeds.entries(&dated, "").unwrap();
+		assert_eq!(2, entries.len());
+		assert_eq!("urn:uuid:1234", entries[0].id);
+		assert_eq!("https://example.com/notes/orca.html", entries[1].id);
+		assert_eq!(
+			"<p>a <a href=\"https://example.com/index.html\">vm</a></p>",
+			entries[0].content
+		);
+
+		// the elements rfc 4287 says a feed and its entries have to have
+		let atom = feeds.atom("notes/", &entries);
+		let paths = elements(&atom);
+		for required in ["feed/id", "feed/title", "feed/updated", "feed/author/name"] {
+			assert_eq!(1, count(&paths, required), "{}", required);
+		}
+		for required in [
+			"feed/entry/id",
+			"feed/entry/title",
+			"feed/entry/updated",
+			"feed/entry/content",
+		] {
+			assert_eq!(2, count(&paths, required), "{}", required);
+		}
+		assert!(atom.contains("<title>Mineral Existence: notes</title>"));
+		assert!(atom.contains("<link href=\"https://example.com/notes/feed.xml\" rel=\"self\""));
+		assert!(atom.contains("<title>uxn &amp; varvara</title>"));
+		assert!(atom.contains("<updated>1970-01-01T02:00:20+02:00</updated>"));
+		assert!(atom.contains("<content type=\"html\">&lt;p&gt;a &lt;a href=&quot;https://example.com/index.html&quot;&gt;"));
+
+		// and the ones rss 2.0 does
+		let rss = feeds.rss("", &entries);
+		let paths = elements(&rss);
+		for required in [
+			"rss/channel",
+			"rss/channel/title",
+			"rss/channel/link",
+			"rss/channel/description",
+		] {
+			assert_eq!(1, count(&paths, required), "{}", required);
+		}
+		assert_eq!(2, count(&paths, "rss/channel/item/guid"));
+		assert!(rss.contains("<guid isPermaLink=\"false\">urn:uuid:1234</guid>"));
+		assert!(
+			rss.contains("<guid isPermaLink=\"true\">https://example.com/notes/orca.html</guid>")
+		);
+		assert!(rss.contains("<pubDate>Thu, 01 Jan 1970 02:00:20 +0200</pubDate>"));
+		assert!(rss.contains("<managingEditor>benh@example.com (benh)</managingEditor>"));
+
+		// excerpts are the page's `description`, or the start of its text
+		feeds.full = false;
+		let entries = feeds.entries(&dated, "notes/").unwrap();
+		assert_eq!("a vm", entries[0].content);
+		assert_eq!("a &lt;livecoding&gt; language", entries[1].content);
+		assert_eq!(
+			2,
+			count(&elements(&feeds.atom("", &entries)), "feed/entry/summary")
+		);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
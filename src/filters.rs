@@ -0,0 +1,338 @@
+use crate::utils::date;
+use std::collections::HashMap;
+use toml::Value;
+
+/*
+a filter takes the value on its left and the arguments written after its name,
+so `{{ page.title | truncate 20 }}` calls the `truncate` filter with `page.title` and `[20]`
+*/
+pub type Filter = Box<dyn Fn(&Value, &[Value]) -> Result<Value, String>>;
+
+/*
+every filter templates can use, by name
+the built in ones are added by `Filters::new`, and more can be added with `register`
+`date` writes dates at `offset`, seconds east of utc, so it agrees with `{{date}}`, -> dates.rs
+*/
+pub struct Filters {
+	filters: HashMap<String, Filter>,
+}
+
+impl Filters {
+	pub fn new(offset: i64) -> Filters {
+		let mut filters = Filters {
+			filters: HashMap::new(),
+		};
+		filters.register("date", move |v, args| date_filter(v, args, offset));
+		filters.register("upper", |v, _| Ok(Value::String(text(v)?.to_uppercase())));
+		filters.register("lower", |v, _| Ok(Value::String(text(v)?.to_lowercase())));
+		filters.register("truncate", truncate);
+		filters.register("escape", |v, _| Ok(Value::String(escape(&text(v)?))));
+		filters.register("slugify", |v, _| Ok(Value::String(slugify(&text(v)?))));
+		filters.register("default", default);
+		filters.register("join", join);
+		filters.register("length", length);
+		filters.register("sort", sort);
+		filters.register("reverse", reverse);
+		filters.register("limit", limit);
+		filters
+	}
+
+	pub fn register<F>(&mut self, name: &str, filter: F)
+	where
+		F: Fn(&Value, &[Value]) -> Result<Value, String> + 'static,
+	{
+		self.filters.insert(name.to_string(), Box::new(filter));
+	}
+
+	pub fn apply(&self, name: &str, value: &Value, args: &[Value]) -> Result<Value, String> {
+		match self.filters.get(name) {
+			Some(filter) => filter(value, args),
+			None => Err(format!("there is no filter named `{}`", name)),
+		}
+	}
+}
+
+// dates in utc
+impl Default for Filters {
+	fn default() -> Self {
+		Self::new(0)
+	}
+}
+
+// the text form of a value, for filters that work on text
+fn text(value: &Value) -> Result<String, String> {
+	match value {
+		Value::String(s) => Ok(s.clone()),
+		Value::Integer(i) => Ok(i.to_string()),
+		Value::Float(f) => Ok(f.to_string()),
+		Value::Boolean(b) => Ok(b.to_string()),
+		Value::Datetime(d) => Ok(d.to_string()),
+		Value::Array(_) | Value::Table(_) => Err(String::from("expected text, found a list")),
+	}
+}
+
+fn int_arg(args: &[Value], i: usize, default: i64) -> Result<i64, String> {
+	match args.get(i) {
+		None => Ok(default),
+		Some(Value::Integer(n)) => Ok(*n),
+		Some(other) => text(other)?
+			.parse::<i64>()
+			.map_err(|_| format!("expected a number, found `{}`", other)),
+	}
+}
+
+fn list(value: &Value) -> Result<&Vec<Value>, String> {
+	match value {
+		Value::Array(a) => Ok(a),
+		_ => Err(String::from("expected a list")),
+	}
+}
+
+/*
+formats a date with strftime style specifiers (`%Y-%m-%d` if none is given)
+the date can be seconds since the unix epoch, or text like `2023-05-09`,
+and is written at `offset` unless it's text without an offset, which is taken to already be there
+*/
+fn date_filter(value: &Value, args: &[Value], offset: i64) -> Result<Value, String> {
+	let secs = match value {
+		Value::Integer(i) => i + offset,
+		_ => {
+			let t = text(value)?;
+			let parsed = date::parse(&t).ok_or_else(|| format!("`{}` isn't a date", t));
+			match t.parse::<i64>() {
+				Ok(i) => i + offset,
+				Err(_) if date::has_offset(&t) => parsed? + offset,
+				Err(_) => parsed?,
+			}
+		}
+	};
+	let format = match args.first() {
+		Some(f) => text(f)?,
+		None => String::from("%Y-%m-%d"),
+	};
+	Ok(Value::String(date::format(secs, &format)))
+}
+
+// cuts text down to the given number of characters, ending it with `...` (or the second argument)
+fn truncate(value: &Value, args: &[Value]) -> Result<Value, String> {
+	let t = text(value)?;
+	let length = int_arg(args, 0, 80)?.max(0) as usize;
+	let ending = match args.get(1) {
+		Some(e) => text(e)?,
+		None => String::from("..."),
+	};
+	if t.chars().count() <= length {
+		return Ok(Value::String(t));
+	}
+	let cut = t.chars().take(length).collect::<String>();
+	Ok(Value::String([cut.trim_end(), &ending].concat()))
+}
+
+pub fn escape(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			'\'' => out.push_str("&#39;"),
+			_ => out.push(c),
+		}
+	}
+	out
+}
+
+// lowercase letters and numbers, with everything else turned into single dashes
+pub fn slugify(s: &str) -> String {
+	let mut out = String::new();
+	for c in s.chars().flat_map(|c| c.to_lowercase()) {
+		if c.is_alphanumeric() {
+			out.push(c);
+		} else if !out.is_empty() && !out.ends_with('-') {
+			out.push('-');
+		}
+	}
+	out.trim_end_matches('-').to_string()
+}
+
+// swaps empty text, lists and tables for the argument
+fn default(value: &Value, args: &[Value]) -> Result<Value, String> {
+	let empty = match value {
+		Value::String(s) => s.is_empty(),
+		Value::Array(a) => a.is_empty(),
+		Value::Table(t) => t.is_empty(),
+		_ => false,
+	};
+	match (empty, args.first()) {
+		(true, Some(d)) => Ok(d.clone()),
+		_ => Ok(value.clone()),
+	}
+}
+
+fn join(value: &Value, args: &[Value]) -> Result<Value, String> {
+	let separator = match args.first() {
+		Some(s) => text(s)?,
+		None => String::from(", "),
+	};
+	let items = list(value)?
+		.iter()
+		.map(text)
+		.collect::<Result<Vec<_>, _>>()?;
+	Ok(Value::String(items.join(&separator)))
+}
+
+fn length(value: &Value, _: &[Value]) -> Result<Value, String> {
+	let n = match value {
+		Value::Array(a) => a.len(),
+		Value::Table(t) => t.len(),
+		_ => text(value)?.chars().count(),
+	};
+	Ok(Value::Integer(n as i64))
+}
+
+/*
+sorts a list, either by its items or by the given key of each item (like `sort "title"`)
+numbers are compared as numbers and everything else as text
+*/
+fn sort(value: &Value, args: &[Value]) -> Result<Value, String> {
+	let key = match args.first() {
+		Some(k) => Some(text(k)?),
+		None => None,
+	};
+	let by = |v: &Value| -> Value {
+		match (&key, v) {
+			(Some(k), Value::Table(t)) => t.get(k).cloned().unwrap_or(Value::String(String::new())),
+			_ => v.clone(),
+		}
+	};
+	let mut items = list(value)?.clone();
+	items.sort_by(|a, b| compare(&by(a), &by(b)));
+	Ok(Value::Array(items))
+}
+
+pub fn compare(a: &Value, b: &Value) -> std::cmp::Ordering {
+	let number = |v: &Value| match v {
+		Value::Integer(i) => Some(*i as f64),
+		Value::Float(f) => Some(*f),
+		Value::String(s) => s.parse::<f64>().ok(),
+		_ => None,
+	};
+	match (number(a), number(b)) {
+		(Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(std::cmp::Ordering::Equal),
+		_ => text(a)
+			.unwrap_or_default()
+			.cmp(&text(b).unwrap_or_default()),
+	}
+}
+
+fn reverse(value: &Value, _: &[Value]) -> Result<Value, String> {
+	match value {
+		Value::Array(a) => Ok(Value::Array(a.iter().rev().cloned().collect())),
+		_ => Ok(Value::String(text(value)?.chars().rev().collect())),
+	}
+}
+
+// keeps the first n items of a list
+fn limit(value: &Value, args: &[Value]) -> Result<Value, String> {
+	let n = int_arg(args, 0, 10)?.max(0) as usize;
+	Ok(Value::Array(list(value)?.iter().take(n).cloned().collect()))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn string(s: &str) -> Value {
+		Value::String(String::from(s))
+	}
+
+	#[test]
+	fn test_text_filters() {
+		let filters = Filters::default();
+		let title = string("Hello, World & Friends!");
+		assert_eq!(
+			string("hello-world-friends"),
+			filters.apply("slugify", &title, &[]).unwrap()
+		);
+		assert_eq!(
+			string("Hello, World &amp; Friends!"),
+			filters.apply("escape", &title, &[]).unwrap()
+		);
+		assert_eq!(
+			string("Hello,..."),
+			filters
+				.apply("truncate", &title, &[Value::Integer(6)])
+				.unwrap()
+		);
+		assert_eq!(
+			string("HELLO, WORLD & FRIENDS!"),
+			filters.apply("upper", &title, &[]).unwrap()
+		);
+	}
+
+	#[test]
+	fn test_date_filter() {
+		let filters = Filters::default();
+		let format = [string("%d %B %Y")];
+		assert_eq!(
+			string("09 May 2023"),
+			filters
+				.apply("date", &string("2023-05-09"), &format)
+				.unwrap()
+		);
+		assert_eq!(
+			string("09 May 2023"),
+			filters
+				.apply("date", &Value::Integer(1683642600), &format)
+				.unwrap()
+		);
+		assert!(filters.apply("date", &string("soon"), &format).is_err());
+
+		// 23:00 utc is the next day two hours east, like `{{date}}` with `utc_offset = "+02:00"`
+		let filters = Filters::new(7200);
+		for date in [
+			Value::Integer(1683673200),
+			string("1683673200"),
+			string("2023-05-09T23:00:00Z"),
+		] {
+			assert_eq!(
+				string("10 May 2023"),
+				filters.apply("date", &date, &format).unwrap()
+			);
+		}
+		assert_eq!(
+			string("09 May 2023"),
+			filters
+				.apply("date", &string("2023-05-09 23:00"), &format)
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn test_list_filters() {
+		let filters = Filters::default();
+		let list = Value::Array(vec![string("b"), string("c"), string("a")]);
+		let sorted = filters.apply("sort", &list, &[]).unwrap();
+		assert_eq!(
+			string("a, b, c"),
+			filters.apply("join", &sorted, &[]).unwrap()
+		);
+		let limited = filters.apply("limit", &list, &[Value::Integer(2)]).unwrap();
+		assert_eq!(
+			Value::Integer(2),
+			filters.apply("length", &limited, &[]).unwrap()
+		);
+		assert!(filters.apply("missing", &list, &[]).is_err());
+	}
+
+	#[test]
+	fn test_register() {
+		let mut filters = Filters::default();
+		filters.register("shout", |v, _| Ok(Value::String(format!("{}!", text(v)?))));
+		assert_eq!(
+			string("hi!"),
+			filters.apply("shout", &string("hi"), &[]).unwrap()
+		);
+	}
+}
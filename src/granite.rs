@@ -31,15 +31,6 @@ impl FromStr for Page {
 	}
 }
 
-impl Page {
-	// returns a new Page struct using the parse_granite function
-	// -> parse_granite(s: &String, debug: bool)
-	pub fn new(s: &str) -> Self {
-		let raw = s.to_string();
-		parse(&raw)
-	}
-}
-
 impl fmt::Display for Page {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		fmt.write_str(&self.content)
@@ -144,19 +135,19 @@ pub fn parse(s: &str) -> Page {
 // 	let t = s;
 // 	let mut lines = t.lines();
 // 	let mut output = Vec::<String>::new();
-// 
+//
 // 	for _ in 0..lines.clone().count() {
 // 		let line = lines.next().unwrap_or("");
 // 		output.push(line);
 // 	}
-// 
+//
 // 	for i in 0..output.len() {
 // 		// add `mut` if doing preprocessing
 // 		let line = output[i].clone();
 // 		/*
 // 		if line != "" {
 // 		  let first = first(&line.to_string()).0;
-// 
+//
 // 		  if first != "[".to_string() && first != "]".to_string(){
 // 			line = ["[ p |", &line, " ]"].concat();
 // 		  }
@@ -164,12 +155,62 @@ pub fn parse(s: &str) -> Page {
 // 		*/
 // 		output[i] = insert(&line, len(&line), "\n");
 // 	}
-// 
+//
 // 	output.concat()
 // }
 
-fn parse_granite(s: &String) -> String {
-	let mut t = s.clone();
+/*
+something wrong with some granite, found before it's parsed
+`line` counts from 0, in the text that was checked
+*/
+pub struct Diagnostic {
+	pub line: usize,
+	pub message: String,
+}
+
+/*
+looks for brackets and quotes that aren't closed, which parse_granite would quietly get wrong
+quotes are counted the same way parse_granite counts them, so a `]` in quotes doesn't close anything
+*/
+pub fn check(s: &str) -> Vec<Diagnostic> {
+	let mut diagnostics = Vec::new();
+	// the lines of the `[`s still open, and of the `"` if one is
+	let mut open = Vec::<usize>::new();
+	let mut quote = None;
+	for (line, text) in s.lines().enumerate() {
+		for c in text.chars() {
+			match c {
+				'"' => quote = if quote.is_some() { None } else { Some(line) },
+				'[' if quote.is_none() => open.push(line),
+				']' if quote.is_none() => match open.pop() {
+					Some(_) => (),
+					None => diagnostics.push(Diagnostic {
+						line,
+						message: String::from("`]` doesn't close anything"),
+					}),
+				},
+				_ => (),
+			}
+		}
+	}
+	if let Some(line) = quote {
+		diagnostics.push(Diagnostic {
+			line,
+			message: String::from("`\"` is never closed"),
+		});
+	}
+	for line in open {
+		diagnostics.push(Diagnostic {
+			line,
+			message: String::from("`[` is never closed"),
+		});
+	}
+	diagnostics.sort_by_key(|d| d.line);
+	diagnostics
+}
+
+pub fn parse_granite(s: &str) -> String {
+	let mut t = s.to_string();
 	let mut elems = Vec::<String>::new();
 	let mut in_quotes = false;
 	let mut in_content = false;
@@ -209,10 +250,7 @@ fn parse_granite(s: &String) -> String {
 					let new_char = &slice(&t, i..i + 1)[..];
 					if new_char == "]" {
 						t = remove(&t, i, 1);
-						let elem = match elems.pop() {
-							Some(e) => e,
-							None => String::from(""),
-						};
+						let elem = elems.pop().unwrap_or_default();
 						let end_tag = &format!("</{}>", elem);
 						t = insert(&t, i, end_tag);
 					}
@@ -220,41 +258,36 @@ fn parse_granite(s: &String) -> String {
 					in_quotes = true;
 				}
 			}
-			"[" => {
-				if !in_quotes {
-					// checks if an open bracket ends with a | or a ]. If the latter, the block is invalid and should not be parsed
-					let mut j = i;
-					let valid = loop {
-						if j > len(&t) {
-							break false;
+			"[" if !in_quotes => {
+				// checks if an open bracket ends with a | or a ]. If the latter, the block is invalid and should not be parsed
+				let mut j = i;
+				let valid = loop {
+					if j > len(&t) {
+						break false;
+					}
+					let test_char = &slice(&t, j..j + 1)[..];
+					match test_char {
+						"|" => {
+							break true;
 						}
-						let test_char = &slice(&t, j..j + 1)[..];
-						match test_char {
-							"|" => {
-								break true;
-							}
-							"]" => {
-								break false;
-							}
-							_ => (),
+						"]" => {
+							break false;
 						}
-						j += 1;
-					};
-					if valid {
-						in_content = false;
-					} else {
-						invalid_blocks += 1;
+						_ => (),
 					}
+					j += 1;
+				};
+				if valid {
+					in_content = false;
+				} else {
+					invalid_blocks += 1;
 				}
 			}
 			"]" => {
 				// replaces ] with proper tag, or ignores if it's an invalid block
 				if !in_quotes && invalid_blocks < 1 {
 					t = remove(&t, i, 1);
-					let elem = match elems.pop() {
-						Some(e) => e,
-						None => String::from(""),
-					};
+					let elem = elems.pop().unwrap_or_default();
 					let end_tag = &format!("</{}>", elem);
 					t = insert(&t, i, end_tag);
 				}
@@ -290,10 +323,7 @@ fn parse_granite(s: &String) -> String {
 				}
 				"]" => {
 					t = remove(&t, i, 1);
-					let elem = match elems.pop() {
-						Some(e) => e,
-						None => String::from(""),
-					};
+					let elem = elems.pop().unwrap_or_default();
 					let end_tag = &format!("</{}>", elem);
 					t = insert(&t, i, end_tag);
 				}
@@ -346,3 +376,298 @@ fn parse_granite(s: &String) -> String {
 	}
 	t
 }
+
+/*
+a piece of a parsed page: an element with its attributes and what's inside it, or some text
+granite maps 1:1 to html, so the structure of some granite is read back out of the html it's parsed into
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+	Element {
+		tag: String,
+		attrs: Vec<(String, String)>,
+		children: Vec<Node>,
+	},
+	// text as it is in the html, entities and all, or a comment or doctype
+	Text(String),
+}
+
+// elements that never have anything inside them, so they're never closed
+const VOID: [&str; 14] = [
+	"area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+	"track", "wbr",
+];
+
+/*
+reads html, like what parse_granite gives back, into nodes
+close tags that don't close anything are dropped, and elements left open end where their parent does
+*/
+pub fn nodes(html: &str) -> Vec<Node> {
+	// the elements still open, with the top level first, which is an element without a tag
+	let mut open = vec![Node::Element {
+		tag: String::new(),
+		attrs: Vec::new(),
+		children: Vec::new(),
+	}];
+	let close = |open: &mut Vec<Node>| {
+		let element = open.pop().unwrap();
+		add(open, element);
+	};
+	let mut rest = html;
+	while !rest.is_empty() {
+		let text_end = rest.find('<').unwrap_or(rest.len());
+		if text_end > 0 {
+			add(&mut open, Node::Text(rest[..text_end].to_string()));
+			rest = &rest[text_end..];
+			continue;
+		}
+		// comments and doctypes are kept as they are
+		if rest.starts_with("<!") {
+			let end = match rest.strip_prefix("<!--") {
+				Some(comment) => comment.find("-->").map_or(rest.len(), |e| e + 7),
+				None => rest.find('>').map_or(rest.len(), |e| e + 1),
+			};
+			add(&mut open, Node::Text(rest[..end].to_string()));
+			rest = &rest[end..];
+			continue;
+		}
+		let end = match tag_end(rest) {
+			Some(e) => e,
+			None => {
+				add(&mut open, Node::Text(rest.to_string()));
+				break;
+			}
+		};
+		let inner = &rest[1..end];
+		rest = &rest[end + 1..];
+		if let Some(name) = inner.strip_prefix('/') {
+			let name = name.trim().to_lowercase();
+			if let Some(at) = open
+				.iter()
+				.rposition(|n| matches!(n, Node::Element { tag, .. } if *tag == name))
+			{
+				// the top level is never closed, since its name can't match
+				while open.len() > at {
+					close(&mut open);
+				}
+			}
+			continue;
+		}
+		let self_closing = inner.ends_with('/');
+		let inner = inner.trim_end_matches('/');
+		let name_end = inner
+			.find(|c: char| c.is_whitespace())
+			.unwrap_or(inner.len());
+		let tag = inner[..name_end].to_lowercase();
+		let attrs = attributes(&inner[name_end..]);
+		if self_closing || VOID.contains(&tag.as_str()) {
+			add(
+				&mut open,
+				Node::Element {
+					tag,
+					attrs,
+					children: Vec::new(),
+				},
+			);
+			continue;
+		}
+		// scripts and styles are text until they're closed, whatever's in them
+		if tag == "script" || tag == "style" {
+			let end = find_ignoring_case(rest, &format!("</{}", tag)).unwrap_or(rest.len());
+			let children = match &rest[..end] {
+				"" => Vec::new(),
+				text => vec![Node::Text(text.to_string())],
+			};
+			add(
+				&mut open,
+				Node::Element {
+					tag: tag.clone(),
+					attrs,
+					children,
+				},
+			);
+			rest = &rest[end..];
+			rest = match tag_end(rest) {
+				Some(e) => &rest[e + 1..],
+				None => "",
+			};
+			continue;
+		}
+		open.push(Node::Element {
+			tag,
+			attrs,
+			children: Vec::new(),
+		});
+	}
+	while open.len() > 1 {
+		close(&mut open);
+	}
+	match open.pop() {
+		Some(Node::Element { children, .. }) => children,
+		_ => Vec::new(),
+	}
+}
+
+// puts a node at the end of the element that's open
+fn add(open: &mut [Node], node: Node) {
+	if let Some(Node::Element { children, .. }) = open.last_mut() {
+		children.push(node);
+	}
+}
+
+/*
+where `needle`, which is ascii, first appears in `s` in any case
+lowercasing `s` to search it can change how many bytes some characters take, so it's compared byte by byte
+*/
+fn find_ignoring_case(s: &str, needle: &str) -> Option<usize> {
+	s.as_bytes()
+		.windows(needle.len())
+		.position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// where the tag at the start of some html ends, skipping any `>` in quoted attributes
+fn tag_end(html: &str) -> Option<usize> {
+	let mut quote = None;
+	for (i, c) in html.char_indices() {
+		match (c, quote) {
+			('"', None) | ('\'', None) => quote = Some(c),
+			(c, Some(q)) if c == q => quote = None,
+			('>', None) => return Some(i),
+			_ => (),
+		}
+	}
+	None
+}
+
+// reads attributes like ` href="uxn.html" class=note hidden`
+fn attributes(s: &str) -> Vec<(String, String)> {
+	let mut attrs = Vec::new();
+	let mut rest = s.trim_start();
+	while !rest.is_empty() {
+		let name_end = rest
+			.find(|c: char| c == '=' || c.is_whitespace())
+			.unwrap_or(rest.len());
+		let name = rest[..name_end].to_string();
+		rest = rest[name_end..].trim_start();
+		let value = match rest.strip_prefix('=') {
+			Some(value) => {
+				let value = value.trim_start();
+				let (text, after) = match value.chars().next() {
+					Some(q) if q == '"' || q == '\'' => {
+						let end = value[1..].find(q).map_or(value.len(), |e| e + 1);
+						(&value[1..end], value.get(end + 1..).unwrap_or(""))
+					}
+					_ => {
+						let end = value.find(char::is_whitespace).unwrap_or(value.len());
+						(&value[..end], &value[end..])
+					}
+				};
+				rest = after.trim_start();
+				text.to_string()
+			}
+			None => String::new(),
+		};
+		if !name.is_empty() {
+			attrs.push((name, value));
+		}
+	}
+	attrs
+}
+
+/*
+writes nodes back out as html, the other way from `nodes`
+*/
+pub fn render(nodes: &[Node]) -> String {
+	let mut html = String::new();
+	for node in nodes {
+		match node {
+			Node::Text(text) => html.push_str(text),
+			Node::Element {
+				tag,
+				attrs,
+				children,
+			} => {
+				html.push('<');
+				html.push_str(tag);
+				for (name, value) in attrs {
+					html.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;")));
+				}
+				html.push('>');
+				if !VOID.contains(&tag.as_str()) {
+					html.push_str(&render(children));
+					html.push_str(&format!("</{}>", tag));
+				}
+			}
+		}
+	}
+	html
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_nodes() {
+		let html = "<p class=\"intro\">a <a href='uxn.html'>link</a><br></p><!-- note --><ul><li>one<li>two</ul></b>";
+		let nodes = nodes(html);
+		let text = |t: &str| Node::Text(t.to_string());
+		assert_eq!(
+			vec![
+				Node::Element {
+					tag: String::from("p"),
+					attrs: vec![(String::from("class"), String::from("intro"))],
+					children: vec![
+						text("a "),
+						Node::Element {
+							tag: String::from("a"),
+							attrs: vec![(String::from("href"), String::from("uxn.html"))],
+							children: vec![text("link")],
+						},
+						Node::Element {
+							tag: String::from("br"),
+							attrs: Vec::new(),
+							children: Vec::new(),
+						},
+					],
+				},
+				text("<!-- note -->"),
+				Node::Element {
+					tag: String::from("ul"),
+					attrs: Vec::new(),
+					children: vec![Node::Element {
+						tag: String::from("li"),
+						attrs: Vec::new(),
+						children: vec![
+							text("one"),
+							Node::Element {
+								tag: String::from("li"),
+								attrs: Vec::new(),
+								children: vec![text("two")],
+							},
+						],
+					}],
+				},
+			],
+			nodes
+		);
+		assert_eq!(
+			"<p class=\"intro\">a <a href=\"uxn.html\">link</a><br></p><!-- note --><ul><li>one<li>two</li></li></ul>",
+			render(&nodes)
+		);
+
+		// characters that change length when they're lowercased don't move where a script ends
+		let html = "<script>let s = \"İ\";</SCRIPT><p>after</p>";
+		let script = super::nodes(html);
+		assert_eq!(
+			Node::Element {
+				tag: String::from("script"),
+				attrs: Vec::new(),
+				children: vec![text("let s = \"İ\";")],
+			},
+			script[0]
+		);
+		assert_eq!(2, script.len());
+		assert_eq!(None, find_ignoring_case("K", "</style"));
+	}
+}
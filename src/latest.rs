@@ -0,0 +1,292 @@
+use crate::dates::Dates;
+use crate::pipeline::{PageCtx, Stage, Transform};
+use crate::plugins::{self, Tag};
+use crate::site::{self, Source};
+use crate::template::Templates;
+use std::cell::RefCell;
+use toml::value::Table;
+use toml::Value;
+
+/*
+replaces `{{latest}}` with a list of the latest pages on the site, from every directory:
+```
+{{latest}}                   as many as `latest_length` in [values], or every page if it isn't set
+{{latest 5}}                 or `count: 5`
+{{latest section: notes}}    only pages in `notes/`
+{{latest sort: title}}       by `date` (the default, -> dates.rs), `modified`, `created` or `title`
+{{latest item: entry}}       each page written with `partials/entry.html`
+```
+pages that can't be dated are left out with a warning,
+and a post-template plugin that handles `latest` replaces it instead
+*/
+pub struct Latest<'a> {
+	sources: &'a [Source],
+	dates: &'a Dates,
+	templates: &'a Templates,
+	// everything from `site::globals`, which item partials can use like templates can
+	globals: &'a Table,
+	// every page with its date, found the first time a list needs them
+	dated: RefCell<Option<Vec<(&'a Source, i64)>>>,
+	// `latest_length` from [values]
+	length: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+	Date,
+	Modified,
+	Created,
+	Title,
+}
+
+impl<'a> Latest<'a> {
+	pub fn new(
+		sources: &'a [Source],
+		dates: &'a Dates,
+		templates: &'a Templates,
+		globals: &'a Table,
+	) -> Latest<'a> {
+		let values = globals.get("site").and_then(|v| v.as_table());
+		let length = match values.and_then(|v| v.get("latest_length")) {
+			Some(Value::Integer(i)) if *i >= 0 => Some(*i as usize),
+			Some(Value::String(s)) => s.parse().ok(),
+			_ => None,
+		};
+		Latest {
+			sources,
+			dates,
+			templates,
+			globals,
+			dated: RefCell::new(None),
+			length,
+		}
+	}
+
+	/*
+	the pages that can be dated, with their dates
+	pages that can't are warned about once, rather than on every page with a list
+	*/
+	fn dated(&self) -> Vec<(&'a Source, i64)> {
+		let mut dated = self.dated.borrow_mut();
+		let sources = self.sources;
+		let dates = self.dates;
+		dated
+			.get_or_insert_with(|| {
+				let mut dated = Vec::new();
+				for source in sources {
+					match dates.of(source) {
+						Ok(date) => dated.push((source, date)),
+						Err(e) => println!(
+							"warning: {} is left out of `{{{{latest}}}}` lists, since it couldn't be dated: {}",
+							source.path, e
+						),
+					}
+				}
+				dated
+			})
+			.clone()
+	}
+
+	/*
+	replaces every `{{latest}}` tag on a page, with links that lead from the page to each one
+	*/
+	pub fn replace(&self, page: &Source, content: &str) -> Result<String, String> {
+		let mut output = content.to_string();
+		for raw in plugins::candidates(content) {
+			let tag = Tag::parse(raw)?;
+			if tag.name != "latest" {
+				continue;
+			}
+			let count = |n: &str| {
+				n.parse::<usize>()
+					.map_err(|_| format!("`{}` isn't a number of pages in `{}`", n, raw))
+			};
+			let mut length = self.length;
+			let mut section = None;
+			let mut sort = Sort::Date;
+			let mut item = None;
+			for arg in &tag.args {
+				length = Some(count(arg)?);
+			}
+			for (name, value) in &tag.named {
+				match name.as_str() {
+					"count" => length = Some(count(value)?),
+					"section" => section = Some(format!("{}/", value.trim_matches('/'))),
+					"sort" => {
+						sort = match value.as_str() {
+							"date" => Sort::Date,
+							"modified" => Sort::Modified,
+							"created" => Sort::Created,
+							"title" => Sort::Title,
+							_ => {
+								return Err(format!(
+									"`{}` can't be sorted by `{}`, only date, modified, created or title",
+									raw, value
+								))
+							}
+						}
+					}
+					"item" => {
+						let name = format!("partials/{}", value);
+						if !self.templates.exists(&name) {
+							return Err(format!(
+								"`{}` has no {}",
+								raw,
+								self.templates.path(&name).display()
+							));
+						}
+						item = Some(name);
+					}
+					_ => return Err(format!("`{}` doesn't take `{}`", raw, name)),
+				}
+			}
+
+			let mut pages = self
+				.dated()
+				.into_iter()
+				.filter(|(source, _)| section.as_ref().is_none_or(|s| source.url.starts_with(s)))
+				.collect::<Vec<_>>();
+			match sort {
+				Sort::Date => pages.sort_by_key(|(_, date)| -date),
+				Sort::Modified => pages.sort_by_key(|(s, _)| std::cmp::Reverse(s.modified)),
+				Sort::Created => pages.sort_by_key(|(s, _)| std::cmp::Reverse(s.created)),
+				Sort::Title => pages.sort_by_key(|(s, _)| title(s).to_lowercase()),
+			}
+			pages.truncate(length.unwrap_or(pages.len()));
+
+			let root = page.root();
+			let mut list = String::from("<ul>\n");
+			for (source, date) in pages {
+				let url = site::url_for(&root, &source.url);
+				let date = self.dates.format(date);
+				match &item {
+					Some(name) => {
+						let mut vars = source.vars();
+						vars.insert(String::from("url"), Value::String(url));
+						let mut scope = self.globals.clone();
+						scope.insert(String::from("page"), Value::Table(vars));
+						scope.insert(String::from("date"), Value::String(date));
+						scope.insert(String::from("root"), Value::String(root.clone()));
+						let rendered = self
+							.templates
+							.render(name, &scope)
+							.map_err(|e| e.to_string())?;
+						list.push_str(&rendered);
+						if !rendered.ends_with('\n') {
+							list.push('\n');
+						}
+					}
+					None => list.push_str(&format!(
+						"\t<li>{} - <a href=\"{}\">{}</a></li>\n",
+						date,
+						url,
+						title(source)
+					)),
+				}
+			}
+			list.push_str("</ul>");
+			output = output.replace(raw, &list);
+		}
+		Ok(output)
+	}
+}
+
+impl Transform for Latest<'_> {
+	fn name(&self) -> String {
+		String::from("latest pages")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::PostTemplate
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		page.page.content = self.replace(page.source, &page.page.content)?;
+		Ok(())
+	}
+}
+
+// a page's `title`, or its url if it doesn't have one
+fn title(source: &Source) -> &str {
+	source.meta("title").unwrap_or(&source.url)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::config::DateConfig;
+	use crate::granite::parse_header;
+	use std::collections::HashMap;
+	use std::fs;
+
+	fn source(url: &str, title: &str, modified: u64) -> Source {
+		Source {
+			path: format!("pages/{}", url.replace(".html", ".gn")),
+			target: format!("docs/{}", url),
+			url: url.to_string(),
+			meta: parse_header(&format!("!meta!\ntitle: {}\n!meta!", title)).meta,
+			modified,
+			created: 0,
+			permalink: url.to_string(),
+		}
+	}
+
+	#[test]
+	fn test_latest() {
+		let sources = vec![
+			source("index.html", "home", 30),
+			source("notes/uxn.html", "uxn", 20),
+			source("notes/deep/orca.html", "orca", 10),
+			// a page with a `date` that isn't one is left out
+			Source {
+				meta: parse_header("!meta!\ntitle: draft\ndate: someday\n!meta!").meta,
+				..source("notes/draft.html", "draft", 40)
+			},
+		];
+		let dates = Dates::new(&DateConfig {
+			format: String::from("%s"),
+			utc_offset: String::from("UTC"),
+			sources: vec![String::from("meta"), String::from("modified")],
+			formats: HashMap::new(),
+		})
+		.unwrap();
+		let dir = std::env::temp_dir().join(format!("pillar-latest-{}", std::process::id()));
+		fs::create_dir_all(dir.join("partials")).unwrap();
+		fs::write(
+			dir.join("partials/entry.html"),
+			"<li><a href=\"{{page.url}}\">{{page.title}}</a> {{date}} on {{site.name}}</li>",
+		)
+		.unwrap();
+		let templates = Templates::new(&format!("{}/", dir.display()));
+		let mut values = Table::new();
+		values.insert(String::from("latest_length"), Value::Integer(2));
+		values.insert(String::from("name"), Value::String(String::from("uxn")));
+		let mut globals = Table::new();
+		globals.insert(String::from("site"), Value::Table(values));
+		let latest = Latest::new(&sources, &dates, &templates, &globals);
+
+		// links lead back up from a page two directories down
+		assert_eq!(
+			"<ul>\n\t<li>30 - <a href=\"../../index.html\">home</a></li>\n\t<li>20 - <a href=\"../../notes/uxn.html\">uxn</a></li>\n</ul>",
+			latest.replace(&sources[2], "{{latest}}").unwrap()
+		);
+		assert_eq!(
+			"<ul>\n<li><a href=\"notes/deep/orca.html\">orca</a> 10 on uxn</li>\n</ul>",
+			latest
+				.replace(
+					&sources[0],
+					"{{latest section: notes sort: title count: 1 item: entry}}"
+				)
+				.unwrap()
+		);
+		// the pages are dated once for every list, so the draft is only warned about once
+		assert_eq!(3, latest.dated.borrow().as_ref().unwrap().len());
+		assert!(latest
+			.replace(&sources[0], "{{latest sort: size}}")
+			.is_err());
+		assert!(latest
+			.replace(&sources[0], "{{latest item: missing}}")
+			.is_err());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
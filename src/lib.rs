@@ -0,0 +1,19 @@
+/*
+pillar as a library, for building sites with steps of your own:
+a `Transform` is one step in building a page, which can be added to `Pipeline::builtin` with `Pipeline::add`
+and run on each page's `PageCtx`, -> pipeline.rs
+*/
+pub mod config;
+pub mod dates;
+pub mod feeds;
+pub mod filters;
+pub mod granite;
+pub mod latest;
+pub mod pipeline;
+pub mod plugins;
+pub mod progress;
+pub mod site;
+pub mod template;
+pub mod utils;
+
+pub use pipeline::{PageCtx, Pipeline, Stage, Transform};
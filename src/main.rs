@@ -1,21 +1,16 @@
 // use core::ops::Range;
 // use std::cmp::Ordering;
+use pillar::config::Config;
+use pillar::dates::Dates;
+use pillar::feeds::Feeds;
+use pillar::filters::Filters;
+use pillar::plugins::{self, Plugins};
+use pillar::site;
+use pillar::template::*;
+use pillar::{PageCtx, Pipeline, Stage};
 use std::env;
 use std::fs;
-use std::io::prelude::*;
-use std::os::unix::fs::MetadataExt;
-use std::process::{Command, Stdio};
-use std::time::SystemTime;
-use std::{fs::File, io::ErrorKind};
-use toml::Value;
-mod granite;
-mod progress;
-mod utils;
-use granite::*;
-use lazy_static::lazy_static;
-use regex::Regex;
-use utils::text::*;
-use walkdir::{DirEntry, WalkDir};
+use std::path::Path;
 
 // #[macro_use]
 // extern crate lazy_static;
@@ -40,352 +35,180 @@ fn usage() {
 	println!("{}", HELP_MENU);
 }
 
-fn is_not_hidden(entry: &DirEntry) -> bool {
-	entry
-		.file_name()
-		.to_str()
-		.map(|s| entry.depth() == 0 || !s.starts_with('.'))
-		.unwrap_or(false)
-}
-
 fn main() -> std::io::Result<()> {
 	// flags to define program behaviour
 	let mut should_build = false;
 	let mut build_all = false;
+	let mut explain = false;
+	let mut plugin_cache = true;
 
 	let args: Vec<String> = env::args().collect();
 	match &args[..] {
 		[_] => usage(),
 		// single-command options: verion info, help page, and normal build
-		[_, cmd] => match cmd.as_str() {
+		// build can be followed by options: `--all` to build every page, `--explain` to say which template each page uses,
+		// and `--no-plugin-cache` to run every plugin instead of using what they gave last time
+		[_, cmd, opts @ ..] => match cmd.as_str() {
 			"-V" | "--version" => println!("Version: {}", VERSION),
 			"-h" | "--help" => usage(),
 			"build" => {
 				should_build = true;
+				build_all = opts.iter().any(|o| o == "--all");
+				explain = opts.iter().any(|o| o == "--explain");
+				plugin_cache = !opts.iter().any(|o| o == "--no-plugin-cache");
 			}
 			// clean does nothing right now...
 			"clean" => (),
 			_ => println!("{}", HELP_MENU),
 		},
-		// command with option, allows for build command with debug flag
-		[_, cmd, opt] => match cmd.as_str() {
-			"-V" | "--version" => println!("Version: {}", VERSION),
-			"-h" | "--help" => usage(),
-			"build" => {
-				should_build = true;
-				build_all = opt.as_str() == "--all";
-			}
-			// this clean also does nothing right now...
-			"clean" => (),
-			_ => println!("{}", HELP_MENU),
-		},
 		_ => usage(),
 	}
 
+	// set when a page fails to build, so the next run tries it again
+	let mut failed = false;
+
 	if should_build {
 		// a config struct has path information and a last run date
 		let config = Config::new().unwrap();
-		// uses config info to go through granite directory files and build them into html
-
-		let walk = WalkDir::new(&config.granite_path)
-			.into_iter()
-			.filter_entry(|e| is_not_hidden(e))
-			.filter_map(|v| v.ok());
-
-		let paths = walk.filter_map(|x| {
-			if !fs::metadata(x.path())
-				.expect("failure to parse path")
-				.is_dir()
-			{
-				Some(x)
-			} else {
-				None
+		// -> dates.rs:[Dates::new(config: &DateConfig)]
+		let dates = match Dates::new(&config.dates) {
+			Ok(d) => d,
+			Err(e) => {
+				println!("failed to read [dates] in .pillar.toml: {}", e);
+				std::process::exit(1);
 			}
-		});
-
-		let mut path_strs = Vec::<String>::new();
-		for path in paths {
-			path_strs.push(format!("{:#?}", path.path()))
-		}
-
-		path_strs = path_strs
+		};
+		// templates are parsed once, the first time a page uses them
+		let mut templates = Templates::new(&config.template_path);
+		// the `date` filter writes dates at the same offset as `{{date}}`
+		templates.filters = Filters::new(dates.offset());
+		// `absolute` needs to know the site's url, so it's added here rather than with the other filters
+		let base_url = config.base_url.clone();
+		templates
+			.filters
+			.register("absolute", move |v, _| match v.as_str() {
+				Some(path) => Ok(toml::Value::String(site::absolute(
+					base_url.as_deref(),
+					path,
+				))),
+				None => Err(String::from("expected text")),
+			});
+		// reads every page's meta header up front, so pages can list the other pages
+		// -> site.rs:[index(config: &Config)]
+		let sources = site::index(&config)?;
+		let globals = site::globals(&config, &sources)?;
+		// which template each page uses, and why
+		// -> template.rs:[choose(config: &Config, templates: &Templates, source: &Source)]
+		let chosen = sources
 			.iter()
-			.map(|x| slice(x, 1..len(x) - 1))
+			.map(|s| choose(&config, &templates, s))
 			.collect::<Vec<_>>();
-
-		for path_str in path_strs {
-			let path = std::path::Path::new(&path_str);
-			let prefix = path.parent().unwrap();
-			let prefix_string = format!("{}", prefix.display());
-			if prefix_string
-				!= slice(
-					&config.granite_path.to_string(),
-					0..len(&config.granite_path.to_string()) - 1,
-				) {
-				let dir_target = [
-					config.html_path.clone(),
-					slice(
-						&prefix_string,
-						len(&config.granite_path.to_string())..len(&prefix_string),
-					),
-				]
-				.concat();
-				std::fs::create_dir_all(dir_target).unwrap();
+		let options = plugins::Options {
+			dir: env::current_dir()?,
+			all: build_all,
+			cache: plugin_cache,
+			templates: sources
+				.iter()
+				.zip(&chosen)
+				.map(|(s, (name, _))| (s.path.clone(), name.clone()))
+				.collect(),
+		};
+		// -> plugins/manifest.rs:[discover(dir: &str)]
+		let plugins = match Plugins::new(&config, &sources, options) {
+			Ok(p) => p,
+			Err(e) => {
+				println!("failed to load plugins: {}", e);
+				std::process::exit(1);
 			}
+		};
+
+		// -> feeds.rs:[Feeds::new(config: &Config, dates: &Dates)]
+		let feeds = match Feeds::new(&config, &dates) {
+			Ok(f) => f,
+			Err(e) => {
+				println!("failed to read [feeds] in .pillar.toml: {}", e);
+				std::process::exit(1);
+			}
+		};
+		// every step a page goes through, in order
+		let pipeline = Pipeline::builtin(&config, &templates, &globals, &sources, &dates, &plugins);
+		if explain {
+			println!("each page is built by:");
+			for step in pipeline.explain() {
+				println!("\t{}", step);
+			}
+			let site = plugins.names(Stage::Site);
+			if !site.is_empty() {
+				println!("and then site plugins run: {}", site.join(", "));
+			}
+			if let Some(feeds) = &feeds {
+				println!("and feeds are written to {}", feeds.files().join(", "));
+			}
+		}
 
-			// normalizes path str
-			// let path = format!("{:?}", entry.path());
-			// let path_str = slice(&path, 1..len(&path) - 1);
-
-			// gets and formats file metadata, modified and creation time
-			let meta = fs::metadata(&path_str)?;
-			let modified = meta.mtime() as u64;
-			let created = meta
-				.created()
-				.unwrap()
-				.duration_since(SystemTime::UNIX_EPOCH)
-				.unwrap()
-				.as_secs();
-
-			// reads file to str and generates header variables from the `parse_header(&String)` function
-			let mut static_build = false;
-			let contents =
-				fs::read_to_string(&path_str).expect("Something went wrong reading a granite file");
-			// -> utils.rs:[parse_header(l: &String)]
-			let page_vars = parse_header(&contents).meta;
-			for pvar in page_vars {
-				if pvar.name == "static" {
-					static_build = true;
-				}
+		// the pages built this time, for site plugins
+		let mut built = Vec::new();
+		// uses config info to go through granite directory files and build them into html
+		for (source, (template_name, reason)) in sources.iter().zip(&chosen) {
+			let static_build = source.meta("static").is_some();
+			if explain {
+				println!("{} uses `{}`: {}", source.path, template_name, reason);
 			}
 
 			// re-builds the file if it was modified after the last build, or if it's a static page
-			if (modified > config.last_run) | static_build | build_all {
-				// formats target string to look like html_path/file.html
-				let target = [
-					config.html_path.clone(),
-					slice(
-						&path_str,
-						len(&config.granite_path.to_string())..len(&path_str) - 2,
-					),
-					String::from("html"),
-				]
-				.concat();
+			if (source.modified > config.last_run) | static_build | build_all {
+				let target = &source.target;
+				if let Some(dir) = Path::new(target).parent() {
+					fs::create_dir_all(dir)?;
+				}
 				// just changes what symbol pillar outputs when printing build status
-				if created > config.last_run {
+				if source.created > config.last_run {
 					println!("+ {}", target);
 				} else {
 					println!("~ {}", target);
 				}
 
-				// parses content into Page
-				// -> utils.rs:[Page::new(s: &str, debug: bool)]
-				let page = Page::new(&contents);
-				// makes progress bars on different lines
-				println!();
-
-				let mut templated_string = templated(&config, &page);
-				//This is where plugins are run
-				templated_string = run_plugins(&config, &path_str, &templated_string)?;
+				// pre-parse plugins get the granite after its meta header, which is then checked and parsed,
+				// post-parse plugins get the html from it, and post-template plugins get the whole page
+				// -> pipeline.rs:[Pipeline::builtin(config, templates, globals, sources, dates, plugins)]
+				let contents = fs::read_to_string(&source.path)
+					.expect("Something went wrong reading a granite file");
+				let mut page = PageCtx::new(source, template_name, contents);
+				if let Err(e) = pipeline.run(&mut page) {
+					println!("failed to build {}: {}", target, e);
+					failed = true;
+					continue;
+				}
 				// let completed = replace(&templated_string, "{{date}}", &short_date);
-				match fs::write(&target, templated_string) {
-					Ok(_) => (),
-					Err(e) => println!("failed to write to {}: {}", &target, e),
+				match fs::write(target, page.page.content) {
+					Ok(_) => built.push(source.path.clone()),
+					Err(e) => println!("failed to write to {}: {}", target, e),
 				};
 			}
 		}
-	}
-
-	Config::update_time();
-	Ok(())
-}
-
-fn run_plugins(config: &Config, path_str: &str, contents: &String) -> std::io::Result<String> {
-	let mut output = [path_str, "\n", contents].concat();
-
-	lazy_static! {
-		static ref RE: Regex = Regex::new(r"\{\{([^\}]+)\}\}").unwrap();
-	}
-
-	let mut plugins: Vec<String> = RE
-		.find_iter(contents)
-		.map(|m| slice(m.as_str(), 2..len(m.as_str()) - 2))
-		.collect();
-
-	while !plugins.is_empty() {
-		println!("plugins: {:?}", plugins);
-		for plugin in &plugins {
-			println!("{}", &plugin);
-			let script_str = format!("./{}{}", &config.plugin_path, plugin);
-
-			// run script
-			let process = match Command::new(script_str)
-				.stdin(Stdio::piped())
-				.stdout(Stdio::piped())
-				.spawn()
-			{
-				Err(why) => panic!("couldn't spawn process: {}", why),
-				Ok(process) => process,
-			};
-
-			process
-				.stdin
-				.unwrap()
-				.write_all(output.as_bytes())
-				.expect("couldn't write to script stdin");
-
-			let mut s = String::new();
-			process
-				.stdout
-				.unwrap()
-				.read_to_string(&mut s)
-				.expect("couldn't read script stdout");
-
-			output = s;
-		}
 
-		plugins = RE
-			.find_iter(&output)
-			.filter_map(|m| Some(m.as_str()))
-			.map(|m| slice(m, 2..len(m) - 2))
-			.collect();
-	}
-
-	// removes path str added to beginning of file...?
-	let mut final_string = String::new();
-	for i in 0..len(&output) {
-		let char = &slice(&output, i..i + 1);
-		if char == "\n" {
-			final_string = slice(&output, i + 1..len(&output));
-			break;
-		}
-	}
-	Ok(final_string)
-}
-
-/*
-Takes a config file (for the template path) and a page and outputs the html with everything
-*/
-fn templated(config: &Config, page: &Page) -> String {
-	// starts with default template file
-	let mut template_file = String::from("default.html");
-	for header_var in &page.meta {
-		if header_var.name == "template" {
-			// if the granite meta header has a template value, sets `template_file` to that
-			template_file = header_var.value.clone();
-			template_file.push_str(".html");
+		// feeds have every page that's been built, now or before
+		if let Some(feeds) = &feeds {
+			if let Err(e) = feeds.write(&sources) {
+				println!("failed to write feeds: {}", e);
+				failed = true;
+			}
 		}
-	}
-	let template_path = [config.template_path.clone(), template_file].concat();
-
-	// gets the contents of the given template file
-	let template_contents = match fs::read_to_string(&template_path) {
-		Ok(c) => c,
-		Err(_) => {
-			// if it can't be loaded, just load the default
-			let mut temp_path = config.template_path.clone();
-			temp_path.push_str("default.html");
-			fs::read_to_string(&temp_path).expect("couldn't load default template")
+		// site plugins run once every page is built
+		if let Err(e) = plugins.run_site(&built) {
+			println!("failed to run site plugins: {}", e);
+			failed = true;
 		}
-	};
-
-	let template_lines: Vec<&str> = template_contents.as_str().lines().collect();
-
-	// figures out how indented the content marker is (non-functional)
-	let mut whitespace = String::new();
-	for l in template_lines {
-		let line = l.to_string();
-		if line.contains("{{content}}") {
-			whitespace = slice(&line, 0..first(&line).1);
+		if let Err(e) = plugins.finish() {
+			println!("failed to stop plugins: {}", e);
+			failed = true;
 		}
 	}
 
-	// replaces content in template
-	let mut con_w_space = String::from("{{content}}");
-	con_w_space = insert(&con_w_space, 0, &whitespace);
-	replace(&template_contents, &con_w_space, &page.content)
-}
-
-struct Config {
-	template_path: String,
-	granite_path: String,
-	html_path: String,
-	plugin_path: String,
-	// music_path: String,
-	// latest_length: usize,
-	last_run: u64,
-}
-
-impl Config {
-	fn new() -> Option<Config> {
-		File::open(".pillar.toml").unwrap_or_else(|error| {
-			if error.kind() == ErrorKind::NotFound {
-				File::create(".pillar.toml").unwrap_or_else(|create_error| {
-					panic!("Problem creating the file: {:?}", create_error);
-				});
-				let default = "[paths]\n\
-	                template_path = \"templates/\"\n\
-	                granite_path = \"pages/\"\n\
-	                html_path = \"docs/\"\n\
-	                plugin_path = \"plugins/\"\n\
-	                music_path = \"/home/user/Music/\"\n\
-	                \n\
-	                [values]\n\
-	                latest_length = 15\n\
-	                last_run = 0";
-				fs::write(".pillar.toml", default).unwrap();
-				File::open(".pillar.toml").unwrap()
-			} else {
-				panic!("Problem opening the file: {:?}", error);
-			}
-		});
-
-		let config_string = fs::read_to_string(".pillar.toml").unwrap();
-		let config = config_string.parse::<Value>().unwrap();
-
-		let template_path = config["paths"]["template_path"].to_string();
-		let granite_path = config["paths"]["granite_path"].to_string();
-		let html_path = config["paths"]["html_path"].to_string();
-		let plugin_path = config["paths"]["plugin_path"].to_string();
-		// let music_path = config["paths"]["music_path"].to_string();
-		// let latest_length = config["values"]["latest_length"]
-		// .to_string()
-		// .parse::<usize>()
-		// .unwrap();
-		let last_run = config["values"]["last_run"]
-			.to_string()
-			.parse::<u64>()
-			.unwrap();
-
-		Some(Config {
-			template_path: slice(&template_path, 1..len(&template_path) - 1),
-			granite_path: slice(&granite_path, 1..len(&granite_path) - 1),
-			html_path: slice(&html_path, 1..len(&html_path) - 1),
-			plugin_path: slice(&plugin_path, 1..len(&plugin_path) - 1),
-			// music_path: slice(&music_path, 1..len(&music_path)-1),
-			// latest_length,
-			last_run,
-		})
+	if failed {
+		std::process::exit(1);
 	}
 
-	fn update_time() {
-		let config_str = &fs::read_to_string(".pillar.toml").unwrap();
-		let now = SystemTime::now()
-			.duration_since(SystemTime::UNIX_EPOCH)
-			.unwrap()
-			.as_secs();
-
-		let mut config_string = String::new();
-		for line in config_str.lines() {
-			if slice(&line.to_string(), 0..8) == "last_run" {
-				config_string.push_str(&format!("last_run = {}\n", now));
-			} else {
-				config_string.push_str(&format!("{}\n", line));
-			}
-		}
-
-		fs::write(".pillar.toml", config_string).unwrap();
-	}
+	Config::update_time();
+	Ok(())
 }
-
-/*  ^(;,;)^   */
@@ -0,0 +1,445 @@
+use crate::config::Config;
+use crate::dates::Dates;
+use crate::granite::{self, Page};
+use crate::latest::Latest;
+use crate::plugins::{self, Plugins};
+use crate::site::Source;
+use crate::template::{self, Templates};
+use crate::utils;
+use std::fmt;
+use toml::value::Table;
+
+/*
+where in a build a transform or plugin runs
+every page goes through the stages in this order, and transforms in the same stage run in the order they were added,
+then `site` is once for the whole site after every page is built, which only plugins run at
+*/
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Stage {
+	PreParse,
+	Parse,
+	PostParse,
+	Template,
+	PostTemplate,
+	Site,
+}
+
+impl Stage {
+	// the stages a plugin can say it runs at in its plugin.toml, parsing and templating are pillar's
+	pub const PLUGINS: [Stage; 4] = [
+		Stage::PreParse,
+		Stage::PostParse,
+		Stage::PostTemplate,
+		Stage::Site,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Stage::PreParse => "pre-parse",
+			Stage::Parse => "parse",
+			Stage::PostParse => "post-parse",
+			Stage::Template => "template",
+			Stage::PostTemplate => "post-template",
+			Stage::Site => "site",
+		}
+	}
+}
+
+impl fmt::Display for Stage {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str(self.name())
+	}
+}
+
+/*
+a page on its way through the pipeline
+`page.content` starts as the granite after the meta header, is html once it's parsed,
+and is the whole page once it's been templated
+*/
+pub struct PageCtx<'a> {
+	pub source: &'a Source,
+	// the template the page is built with
+	pub template: &'a str,
+	// the file as it was read, header and all
+	pub file: String,
+	pub page: Page,
+}
+
+impl<'a> PageCtx<'a> {
+	pub fn new(source: &'a Source, template: &'a str, file: String) -> PageCtx<'a> {
+		let page = granite::parse_header(&file);
+		PageCtx {
+			source,
+			template,
+			file,
+			page,
+		}
+	}
+}
+
+/*
+one step in building a page, like parsing its granite or running plugins on it
+*/
+pub trait Transform {
+	fn name(&self) -> String;
+	fn stage(&self) -> Stage;
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String>;
+
+	// what `--explain` says about it, if there's more to say than its name
+	fn explain(&self) -> String {
+		self.name()
+	}
+}
+
+#[derive(Debug)]
+pub struct TransformError {
+	pub transform: String,
+	pub message: String,
+}
+
+impl fmt::Display for TransformError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		write!(fmt, "{}: {}", self.transform, self.message)
+	}
+}
+
+/*
+every transform a page goes through, in order
+-> Pipeline::builtin for the ones pillar has, which custom transforms are added to with `add`
+*/
+pub struct Pipeline<'a> {
+	transforms: Vec<Box<dyn Transform + 'a>>,
+}
+
+impl Default for Pipeline<'_> {
+	fn default() -> Self {
+		Pipeline::new()
+	}
+}
+
+impl<'a> Pipeline<'a> {
+	pub fn new() -> Pipeline<'a> {
+		Pipeline {
+			transforms: Vec::new(),
+		}
+	}
+
+	/*
+	checks and parses granite, templates the page, fills in dates and the latest pages,
+	and runs plugins between each of those
+	*/
+	pub fn builtin(
+		config: &'a Config,
+		templates: &'a Templates,
+		globals: &'a Table,
+		sources: &'a [Source],
+		dates: &'a Dates,
+		plugins: &'a Plugins<'a>,
+	) -> Pipeline<'a> {
+		let mut pipeline = Pipeline::new();
+		pipeline.add(RunPlugins(plugins, Stage::PreParse));
+		pipeline.add(Check);
+		pipeline.add(Parse);
+		pipeline.add(RunPlugins(plugins, Stage::PostParse));
+		pipeline.add(Template {
+			config,
+			templates,
+			globals,
+		});
+		pipeline.add(Escape);
+		// plugins for `{{date}}` or `{{latest}}` are left to replace them themselves
+		if !plugins.handles(Stage::PostTemplate, "date") {
+			pipeline.add(dates);
+		}
+		if !plugins.handles(Stage::PostTemplate, "latest") {
+			pipeline.add(Latest::new(sources, dates, templates, globals));
+		}
+		pipeline.add(RunPlugins(plugins, Stage::PostTemplate));
+		pipeline
+	}
+
+	// goes after every transform already in its stage, and before any in later stages
+	pub fn add(&mut self, transform: impl Transform + 'a) {
+		let at = self
+			.transforms
+			.iter()
+			.position(|t| t.stage() > transform.stage())
+			.unwrap_or(self.transforms.len());
+		self.transforms.insert(at, Box::new(transform));
+	}
+
+	pub fn run(&self, page: &mut PageCtx) -> Result<(), TransformError> {
+		for transform in &self.transforms {
+			transform.apply(page).map_err(|message| TransformError {
+				transform: transform.name(),
+				message,
+			})?;
+		}
+		Ok(())
+	}
+
+	// what `pillar build --explain` prints about how pages are built
+	pub fn explain(&self) -> Vec<String> {
+		self.transforms
+			.iter()
+			.enumerate()
+			.map(|(i, t)| format!("{}. {} ({})", i + 1, t.explain(), t.stage()))
+			.collect()
+	}
+}
+
+// runs the plugins at one of the stages a page goes through
+// -> plugins.rs:[Plugins::run(&self, stage: Stage, source: &Source, contents: &str)]
+struct RunPlugins<'a>(&'a Plugins<'a>, Stage);
+
+impl Transform for RunPlugins<'_> {
+	fn name(&self) -> String {
+		format!("{} plugins", self.1)
+	}
+
+	fn explain(&self) -> String {
+		let names = self.0.names(self.1);
+		if names.is_empty() {
+			format!("no {} plugins", self.1)
+		} else {
+			format!("{} plugins: {}", self.1, names.join(", "))
+		}
+	}
+
+	// site plugins aren't given pages, so they're never in a pipeline
+	fn stage(&self) -> Stage {
+		self.1
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		page.page.content = self
+			.0
+			.run(self.1, page.source, &page.page.content)
+			.map_err(|e| e.to_string())?;
+		Ok(())
+	}
+}
+
+// prints warnings about the granite, which can still be built
+struct Check;
+
+impl Transform for Check {
+	fn name(&self) -> String {
+		String::from("check granite")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::Parse
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		for warning in diagnose(&page.source.path, &page.file, &page.page.content) {
+			println!("{}", warning);
+		}
+		Ok(())
+	}
+}
+
+// -> granite.rs:[parse_granite(s: &str)]
+struct Parse;
+
+impl Transform for Parse {
+	fn name(&self) -> String {
+		String::from("parse granite")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::Parse
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		page.page.content = granite::parse_granite(&page.page.content);
+		// makes progress bars on different lines
+		println!();
+		Ok(())
+	}
+}
+
+/*
+fills in variables like `{{page.title}}` and `{{content}}` in the page's template,
+anything else is left for plugins
+-> template.rs:[Templates::render(&self, name: &str, vars: &Table)]
+*/
+struct Template<'a> {
+	config: &'a Config,
+	templates: &'a Templates,
+	globals: &'a Table,
+}
+
+impl Transform for Template<'_> {
+	fn name(&self) -> String {
+		String::from("template")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::Template
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		let vars = template::context(self.config, self.globals, page.source, &page.page);
+		page.page.content = self
+			.templates
+			.render(page.template, &vars)
+			.map_err(|e| e.to_string())?;
+		Ok(())
+	}
+}
+
+// -> plugins.rs:[escape(s: &str)]
+struct Escape;
+
+impl Transform for Escape {
+	fn name(&self) -> String {
+		String::from("escape tags")
+	}
+
+	fn stage(&self) -> Stage {
+		Stage::PostTemplate
+	}
+
+	fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+		page.page.content = plugins::escape(&page.page.content);
+		Ok(())
+	}
+}
+
+/*
+checks a page's granite before it's parsed, and says where in the granite file each problem is
+granite changed by pre-parse plugins is matched up with the file line by line,
+so problems in text a plugin added are put near the tag it replaced
+*/
+fn diagnose(path: &str, contents: &str, granite: &str) -> Vec<String> {
+	let origins = utils::lines::origins(contents, granite);
+	granite::check(granite)
+		.into_iter()
+		.map(|d| match origins.get(d.line).copied().flatten() {
+			Some(line) => format!("warning: {}:{}: {}", path, line + 1, d.message),
+			None => {
+				// the line after the last one that's the same as in the file
+				let near = origins[..d.line]
+					.iter()
+					.rev()
+					.find_map(|o| *o)
+					.map_or(0, |l| l + 1);
+				format!(
+					"warning: {}:{}: {}, in granite a pre-parse plugin added",
+					path,
+					near + 1,
+					d.message
+				)
+			}
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::fs;
+
+	struct Append(&'static str, Stage);
+
+	impl Transform for Append {
+		fn name(&self) -> String {
+			self.0.to_string()
+		}
+
+		fn stage(&self) -> Stage {
+			self.1
+		}
+
+		fn apply(&self, page: &mut PageCtx) -> Result<(), String> {
+			if self.0 == "fail" {
+				return Err(String::from("failed on purpose"));
+			}
+			page.page.content.push_str(self.0);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_pipeline() {
+		let mut pipeline = Pipeline::new();
+		pipeline.add(Append("c", Stage::PostTemplate));
+		pipeline.add(Append("a", Stage::PreParse));
+		pipeline.add(Append("b", Stage::PostParse));
+		pipeline.add(Append("d", Stage::PostTemplate));
+		assert_eq!(
+			vec![
+				"1. a (pre-parse)",
+				"2. b (post-parse)",
+				"3. c (post-template)",
+				"4. d (post-template)"
+			],
+			pipeline.explain()
+		);
+
+		let source = Source {
+			path: String::from("pages/test.gn"),
+			target: String::from("docs/test.html"),
+			url: String::from("test.html"),
+			meta: Vec::new(),
+			modified: 0,
+			created: 0,
+			permalink: String::from("test.html"),
+		};
+		let file = String::from("!meta!\ntitle: test\n!meta!\ntext ");
+		let mut page = PageCtx::new(&source, "default.html", file);
+		assert_eq!("title", page.page.meta[0].name);
+		pipeline.run(&mut page).unwrap();
+		assert_eq!("text \nabcd", page.page.content);
+
+		pipeline.add(Append("fail", Stage::Template));
+		let error = pipeline.run(&mut page).unwrap_err();
+		assert_eq!("fail: failed on purpose", error.to_string());
+		assert_eq!("text \nabcdab", page.page.content);
+	}
+
+	#[test]
+	fn test_code() {
+		let (dir, config) = crate::plugins::test::project("pipeline", &[]);
+		fs::create_dir_all(dir.join("templates")).unwrap();
+		fs::write(
+			dir.join("templates/default.html"),
+			"<main>{{content}}</main>",
+		)
+		.unwrap();
+		let templates = Templates::new(&config.template_path);
+		let globals = Table::new();
+		let dates = Dates::new(&config.dates).unwrap();
+		let plugins = Plugins::new(&config, &[], crate::plugins::test::options(&dir)).unwrap();
+		let pipeline = Pipeline::builtin(&config, &templates, &globals, &[], &dates, &plugins);
+		let source = Source {
+			path: String::from("pages/tags.gn"),
+			target: String::from("docs/tags.html"),
+			url: String::from("tags.html"),
+			meta: Vec::new(),
+			modified: 0,
+			created: 0,
+			permalink: String::from("tags.html"),
+		};
+
+		// a tag in a code sample is shown as it's written, rather than being run
+		let file =
+			String::from("!meta!\ntitle: tags\n!meta!\n[p| write [code| {{x}} ] for a plugin ]\n");
+		let mut page = PageCtx::new(&source, "default", file);
+		pipeline.run(&mut page).unwrap();
+		assert!(
+			page.page.content.contains("<code> &#123;{x}} </code>"),
+			"{}",
+			page.page.content
+		);
+
+		// but the same tag anywhere else still needs a plugin
+		let file = String::from("!meta!\ntitle: tags\n!meta!\n[p| write {{x}} for a plugin ]\n");
+		let mut page = PageCtx::new(&source, "default", file);
+		let error = pipeline.run(&mut page).unwrap_err();
+		assert!(error.message.contains("no plugin named `x`"), "{}", error);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,1169 @@
+use crate::config::Config;
+use crate::pipeline::Stage;
+use crate::site::{self, Source};
+use cache::Cache;
+use lazy_static::lazy_static;
+pub use manifest::{Plugin, Protocol, Runtime};
+use regex::Regex;
+use script::Scripts;
+use serde_json::{json, Map, Value as Json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use toml::Value;
+use wasm::Modules;
+use worker::Worker;
+
+mod cache;
+mod manifest;
+mod script;
+mod wasm;
+mod worker;
+
+#[derive(Debug)]
+pub struct PluginError {
+	pub plugin: String,
+	pub message: String,
+}
+
+impl fmt::Display for PluginError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		write!(fmt, "plugin `{}`: {}", self.plugin, self.message)
+	}
+}
+
+/*
+a `{{tag}}` left in a page for a plugin, like `{{latest 5}}` or `{{files dir: "docs/files"}}`
+the first word names the plugin, and the rest are its arguments,
+either on their own (`args`) or with a name (`named`), in quotes if they have spaces
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+	// the whole tag, braces included, which is what the plugin replaces
+	pub raw: String,
+	pub name: String,
+	pub args: Vec<String>,
+	pub named: Vec<(String, String)>,
+}
+
+impl Tag {
+	pub fn parse(raw: &str) -> Result<Tag, String> {
+		let inner = raw
+			.strip_prefix("{{")
+			.and_then(|r| r.strip_suffix("}}"))
+			.unwrap_or(raw);
+
+		// splits on spaces and commas, keeping quoted text together
+		// a `:` after a word makes the word after it a named argument
+		let mut words = Vec::<(String, bool)>::new();
+		let mut chars = inner.chars().peekable();
+		while let Some(&c) = chars.peek() {
+			match c {
+				' ' | '\t' | '\n' | ',' => {
+					chars.next();
+				}
+				':' => {
+					chars.next();
+					match words.last_mut() {
+						Some((word, false)) if !word.ends_with(':') => word.push(':'),
+						_ => return Err(format!("`:` without a name before it in `{}`", raw)),
+					}
+				}
+				'"' | '\'' => {
+					chars.next();
+					let mut word = String::new();
+					loop {
+						match chars.next() {
+							Some('\\') => word.extend(chars.next()),
+							Some(q) if q == c => break,
+							Some(other) => word.push(other),
+							None => return Err(format!("unclosed quote in `{}`", raw)),
+						}
+					}
+					words.push((word, true));
+				}
+				_ => {
+					let mut word = String::new();
+					while let Some(&c) = chars.peek() {
+						if [' ', '\t', '\n', ',', ':', '"', '\''].contains(&c) {
+							break;
+						}
+						word.push(c);
+						chars.next();
+					}
+					words.push((word, false));
+				}
+			}
+		}
+
+		let mut words = words.into_iter();
+		let name = match words.next() {
+			Some((name, false)) if !name.ends_with(':') => name,
+			_ => return Err(format!("`{}` doesn't start with a plugin name", raw)),
+		};
+		let mut args = Vec::new();
+		let mut named = Vec::new();
+		while let Some((word, quoted)) = words.next() {
+			match word.strip_suffix(':') {
+				Some(key) if !quoted => match words.next() {
+					Some((value, _)) => named.push((key.to_string(), value)),
+					None => return Err(format!("`{}` has no value in `{}`", key, raw)),
+				},
+				_ => args.push(word),
+			}
+		}
+		Ok(Tag {
+			raw: raw.to_string(),
+			name,
+			args,
+			named,
+		})
+	}
+}
+
+/*
+how pillar was asked to build the site
+*/
+pub struct Options {
+	// the directory plugins are run in, which has .pillar.toml in it
+	pub dir: PathBuf,
+	// whether every page is being built with `--all`, or just the ones that changed
+	pub all: bool,
+	// false with `--no-plugin-cache`, so every plugin runs
+	pub cache: bool,
+	// the template each page is built with, by its granite file
+	pub templates: HashMap<String, String>,
+}
+
+/*
+runs plugins at each stage of building a page, and once for the whole site at the end
+-> plugins/manifest.rs for how plugins say when they run
+*/
+pub struct Plugins<'a> {
+	config: &'a Config,
+	sources: &'a [Source],
+	// every page, in the form json plugins are given it
+	site: Json,
+	// everything in the plugin directory, in the order it runs
+	plugins: Vec<Plugin>,
+	options: Options,
+	// the worker plugins that have been started, which keep running until `finish`
+	workers: RefCell<HashMap<String, Worker>>,
+	cache: Cache,
+	scripts: Scripts,
+	modules: Modules,
+}
+
+impl<'a> Plugins<'a> {
+	pub fn new(
+		config: &'a Config,
+		sources: &'a [Source],
+		options: Options,
+	) -> Result<Plugins<'a>, PluginError> {
+		let pages = sources.iter().map(page).collect::<Vec<_>>();
+		let plugins = manifest::discover(&config.plugin_path)?;
+		Ok(Plugins {
+			config,
+			sources,
+			site: json!({ "pages": pages }),
+			cache: Cache::new(&config.cache_path, options.cache, &plugins)?,
+			scripts: Scripts::new(&options.dir),
+			modules: Modules::new(config.plugins.wasm_memory),
+			plugins,
+			options,
+			workers: RefCell::new(HashMap::new()),
+		})
+	}
+
+	// the plugins that run at a stage, in order
+	fn at(&self, stage: Stage) -> impl Iterator<Item = &Plugin> {
+		self.plugins.iter().filter(move |p| p.stage == stage)
+	}
+
+	// the names of the plugins that run at a stage, in order, for `--explain`
+	pub fn names(&self, stage: Stage) -> Vec<&str> {
+		self.at(stage).map(|p| p.name.as_str()).collect()
+	}
+
+	// whether a plugin replaces a tag at a stage, so pillar leaves its own version of the tag to it
+	pub fn handles(&self, stage: Stage, tag: &str) -> bool {
+		self.at(stage).any(|p| p.handles(tag))
+	}
+
+	/*
+	finds the plugin that replaces a tag at a stage, making sure it's one pages are allowed to run,
+	since anyone writing a page can write any tag
+	before templating, tags no plugin at that stage handles are left for later,
+	but after it they have to be handled by a post-template plugin in the plugin directory,
+	and the plugin has to be in the [plugins] allow list if there is one
+	*/
+	fn handler(&self, stage: Stage, tag: &Tag) -> Result<Option<&Plugin>, PluginError> {
+		let error = |message: String| {
+			Err(PluginError {
+				plugin: tag.name.clone(),
+				message,
+			})
+		};
+		let name = &tag.name;
+		let plugin = match self.at(stage).find(|p| p.handles(name)) {
+			Some(plugin) => plugin,
+			None if stage != Stage::PostTemplate => return Ok(None),
+			None if name.contains('/') || name.contains('\\') || name.starts_with('.') => {
+				return error(format!(
+					"plugin names can't have `/` or `\\` in them or start with `.`, so `{}` can't be run",
+					name
+				))
+			}
+			None => {
+				return match self.plugins.iter().find(|p| p.handles(name)) {
+					Some(other) => error(format!(
+						"`{}` is replaced by `{}`, which runs at the {} stage, so it can't be left for after templating",
+						tag.raw, other.name, other.stage
+					)),
+					None => error(format!(
+						"there is no plugin named `{}` in {}",
+						name, self.config.plugin_path
+					)),
+				}
+			}
+		};
+		match &self.config.plugins.allow {
+			Some(allow) if !allow.contains(&plugin.name) => error(format!(
+				"`{}` isn't in the [plugins] allow list in .pillar.toml",
+				plugin.name
+			)),
+			_ => Ok(Some(plugin)),
+		}
+	}
+
+	// the tags in some text which plugins at a stage replace, in the order their plugins run
+	fn found(&self, stage: Stage, s: &str) -> Result<Vec<(Tag, usize)>, PluginError> {
+		let tags = match stage {
+			Stage::PostTemplate => tags(s)?,
+			// granite can have `{{` in it for other reasons, which aren't a problem this early
+			_ => candidates(s)
+				.into_iter()
+				.filter_map(|raw| Tag::parse(raw).ok())
+				.collect(),
+		};
+		let mut found = Vec::new();
+		for tag in tags {
+			if let Some(plugin) = self.handler(stage, &tag)? {
+				let position = self.plugins.iter().position(|p| p.name == plugin.name);
+				found.push((tag, position.unwrap_or_default()));
+			}
+		}
+		found.sort_by_key(|(_, position)| *position);
+		Ok(found)
+	}
+
+	/*
+	runs a stage's plugins on some of a page: its granite before it's parsed, the html from it,
+	or the whole page after templating
+	plugins without tags run on every page first, then the plugin for each different tag in the page runs,
+	then the same happens for any new tags the plugins added, until there aren't any, or `rounds` from
+	the config have gone by
+	a plugin that leaves its own tag in the page, or tags still there after the last round,
+	are errors which say which plugins added the tag
+	*/
+	pub fn run(
+		&self,
+		stage: Stage,
+		source: &Source,
+		contents: &str,
+	) -> Result<String, PluginError> {
+		let mut output = contents.to_string();
+		for plugin in self.at(stage).filter(|p| p.every_page()) {
+			output = self.dispatch(plugin, None, Some(source), &output)?;
+		}
+
+		// the plugins that added each tag, in order, empty for tags that were there to start with
+		let mut origins = HashMap::<String, Vec<String>>::new();
+		for _ in 0..self.config.plugins.rounds {
+			let found = self.found(stage, &output)?;
+			if found.is_empty() {
+				return Ok(output);
+			}
+			let names = found.iter().map(|(t, _)| &t.name).collect::<Vec<_>>();
+			println!("{} plugins: {:?}", stage, names);
+			for (tag, _) in &found {
+				println!("{}", &tag.raw);
+				let chain = origins.get(&tag.raw).cloned().unwrap_or_default();
+				let before = self.found(stage, &output)?;
+				output = self
+					.handler(stage, tag)
+					.and_then(|plugin| match plugin {
+						Some(plugin) => self.dispatch(plugin, Some(tag), Some(source), &output),
+						None => Ok(output.clone()),
+					})
+					.map_err(|mut e| {
+						e.message.push_str(&added_by(&chain));
+						e
+					})?;
+
+				let after = self.found(stage, &output)?;
+				if after.iter().any(|(t, _)| t == tag) {
+					return Err(PluginError {
+						plugin: tag.name.clone(),
+						message: format!(
+							"`{}` is still in {} after the plugin ran{}",
+							tag.raw,
+							source.path,
+							added_by(&chain)
+						),
+					});
+				}
+				for (new, _) in after.iter().filter(|t| !before.contains(t)) {
+					let mut chain = chain.clone();
+					chain.push(tag.name.clone());
+					origins.entry(new.raw.clone()).or_insert(chain);
+				}
+			}
+		}
+
+		let left = self.found(stage, &output)?;
+		match left.first() {
+			None => Ok(output),
+			Some((tag, _)) => Err(PluginError {
+				plugin: tag.name.clone(),
+				message: format!(
+					"`{}` is still in {} after {} rounds of plugins{}",
+					tag.raw,
+					source.path,
+					self.config.plugins.rounds,
+					added_by(origins.get(&tag.raw).unwrap_or(&Vec::new()))
+				),
+			}),
+		}
+	}
+
+	/*
+	runs each site plugin once, after every page has been built, with `built` being the pages built this time
+	they're always json plugins, and are given every built page with what it links to and what links to it,
+	but no content of their own, since they're for things like feeds and sitemaps which go in their own files
+	*/
+	pub fn run_site(&self, built: &[String]) -> Result<(), PluginError> {
+		let mut plugins = self.at(Stage::Site).peekable();
+		if plugins.peek().is_none() {
+			return Ok(());
+		}
+		let site = self.index(built);
+		for plugin in plugins {
+			self.run_json(plugin, None, None, &site, "")?;
+		}
+		Ok(())
+	}
+
+	/*
+	every page that's been built, now or in an earlier build, with its links read back out of its html
+	`rebuilt` is whether it was built this time
+	*/
+	fn index(&self, built: &[String]) -> Json {
+		let sources = self
+			.sources
+			.iter()
+			.filter_map(|s| Some((s, fs::read_to_string(&s.target).ok()?)))
+			.map(|(s, html)| (s, site::linked(&html, &s.url)))
+			.collect::<Vec<_>>();
+		let mut pages = Vec::new();
+		for (source, links) in &sources {
+			let linked_from = sources
+				.iter()
+				.filter(|(other, links)| other.url != source.url && links.contains(&source.url))
+				.map(|(other, _)| other.url.clone())
+				.collect::<Vec<_>>();
+			let mut page = page(source);
+			page["rebuilt"] = json!(built.contains(&source.path));
+			page["links"] = json!(links);
+			page["linked_from"] = json!(linked_from);
+			pages.push(page);
+		}
+		json!({ "pages": pages })
+	}
+
+	/*
+	writes the files a site plugin replied with, as `{ "files": { "feed.xml": "..." } }`, into the html directory
+	they have to stay in it, so `../something` or `/etc/something` are errors
+	*/
+	fn write(&self, plugin: &Plugin, files: &Map<String, Json>) -> Result<(), PluginError> {
+		let error = |message: String| PluginError {
+			plugin: plugin.name.clone(),
+			message,
+		};
+		for (path, content) in files {
+			let inside = !path.is_empty()
+				&& Path::new(path)
+					.components()
+					.all(|c| matches!(c, Component::Normal(_)));
+			if !inside {
+				return Err(error(format!(
+					"can't write `{}`, files have to be somewhere in {}",
+					path, self.config.html_path
+				)));
+			}
+			let content = content
+				.as_str()
+				.ok_or_else(|| error(format!("the contents of `{}` should be text", path)))?;
+			let target = Path::new(&self.config.html_path).join(path);
+			if let Some(dir) = target.parent() {
+				fs::create_dir_all(dir)
+					.map_err(|e| error(format!("couldn't make {}: {}", dir.display(), e)))?;
+			}
+			fs::write(&target, content)
+				.map_err(|e| error(format!("couldn't write {}: {}", target.display(), e)))?;
+			println!("+ {}", target.display());
+		}
+		Ok(())
+	}
+
+	/*
+	stops every worker plugin at the end of the build
+	*/
+	pub fn finish(&self) -> Result<(), PluginError> {
+		let mut result = Ok(());
+		for (name, worker) in self.workers.borrow_mut().drain() {
+			let manifest = self
+				.plugins
+				.iter()
+				.find(|p| p.name == name)
+				.and_then(|p| p.timeout);
+			let stopped = worker.stop(self.config.plugins.timeout(&name, manifest));
+			if result.is_ok() {
+				result = stopped;
+			}
+		}
+		result
+	}
+
+	fn dispatch(
+		&self,
+		plugin: &Plugin,
+		tag: Option<&Tag>,
+		source: Option<&Source>,
+		contents: &str,
+	) -> Result<String, PluginError> {
+		match plugin.protocol {
+			Protocol::Legacy => self.run_legacy(plugin, tag, source, contents),
+			Protocol::Json => self.run_json(plugin, tag, source, &self.site, contents),
+		}
+	}
+
+	/*
+	gives the plugin its input on stdin and returns everything it writes to stdout
+	the tag's arguments are passed as command line arguments, named ones as `PILLAR_ARG_<NAME>`
+	environment variables, and the whole tag as `PILLAR_TAG`, so the plugin knows what to replace
+	anything the plugin writes to stderr is printed with the page's name, and it's an error
+	if the plugin exits with a non-zero status, which has its stderr in it, or runs for longer than its timeout
+	*/
+	fn exec(
+		&self,
+		plugin: &Plugin,
+		tag: Option<&Tag>,
+		source: Option<&Source>,
+		input: &str,
+	) -> Result<String, PluginError> {
+		let place = &place(source);
+		let timeout = self.config.plugins.timeout(&plugin.name, plugin.timeout);
+		if plugin.worker {
+			return self.request(plugin, place, input, timeout);
+		}
+		let error = |message: String| PluginError {
+			plugin: plugin.name.clone(),
+			message,
+		};
+		let mut command = self.command(plugin, source);
+		if let Some(tag) = tag {
+			command.args(&tag.args).env("PILLAR_TAG", &tag.raw);
+			for (key, value) in &tag.named {
+				command.env(env_name(key), value);
+			}
+		}
+		let mut process = command
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| error(format!("couldn't start {}: {}", plugin.run.display(), e)))?;
+
+		// stdin is written while stdout and stderr are read, so a plugin that starts writing
+		// before it's read everything doesn't fill a pipe and wait on us forever
+		let mut stdin = process.stdin.take().unwrap();
+		let input = input.to_string();
+		// a plugin can stop reading before the end, which isn't a problem
+		thread::spawn(move || stdin.write_all(input.as_bytes()));
+		let stdout = read_all(process.stdout.take().unwrap());
+		let stderr = read_all(process.stderr.take().unwrap());
+
+		let start = Instant::now();
+		let status = loop {
+			match process.try_wait() {
+				Ok(Some(status)) => break status,
+				Ok(None) if start.elapsed() > timeout => {
+					let _ = process.kill();
+					let _ = process.wait();
+					return Err(error(format!(
+						"was stopped after {} seconds on {}",
+						timeout.as_secs(),
+						place
+					)));
+				}
+				Ok(None) => thread::sleep(Duration::from_millis(5)),
+				Err(e) => return Err(error(format!("couldn't wait for it to finish: {}", e))),
+			}
+		};
+
+		let stderr = stderr.join().unwrap_or_default();
+		let stderr = String::from_utf8_lossy(&stderr);
+		let stdout = stdout.join().unwrap_or_default();
+		if !status.success() {
+			let status = match status.code() {
+				Some(code) => format!("exited with status {}", code),
+				None => String::from("was killed"),
+			};
+			// what it wrote to stderr is usually why, so it goes in the error
+			let output = stderr.trim_end();
+			return Err(error(match output {
+				"" => format!("{} on {}", status, place),
+				_ => format!("{} on {}:\n{}", status, place, output),
+			}));
+		}
+		for line in stderr.lines() {
+			println!("[{} on {}] {}", plugin.name, place, line);
+		}
+		String::from_utf8(stdout)
+			.map_err(|_| error(format!("wrote something that isn't utf-8 on {}", place)))
+	}
+
+	/*
+	the command for running a plugin, with environment variables describing the build,
+	and the page if there is one:
+	`PILLAR_SOURCE`, `PILLAR_OUTPUT` and `PILLAR_URL` are the page's granite file, html file and url,
+	`PILLAR_META` is its meta header as json and `PILLAR_TEMPLATE` is the template it's built with
+	plugins are always run from the directory with .pillar.toml in it, which paths are relative to
+	*/
+	fn command(&self, plugin: &Plugin, source: Option<&Source>) -> Command {
+		let config = self.config;
+		let mut command = Command::new(&plugin.run);
+		command
+			.current_dir(&self.options.dir)
+			.env("PILLAR_STAGE", plugin.stage.name())
+			.env("PILLAR_TEMPLATE_PATH", &config.template_path)
+			.env("PILLAR_GRANITE_PATH", &config.granite_path)
+			.env("PILLAR_HTML_PATH", &config.html_path)
+			.env("PILLAR_PLUGIN_PATH", &config.plugin_path)
+			.env("PILLAR_DATA_PATH", &config.data_path)
+			.env("PILLAR_BUILD_TIME", config.build_time.to_string())
+			.env("PILLAR_LAST_RUN", config.last_run.to_string())
+			.env(
+				"PILLAR_BUILD_MODE",
+				if self.options.all { "all" } else { "changed" },
+			);
+		if let Some(base_url) = &config.base_url {
+			command.env("PILLAR_BASE_URL", base_url);
+		}
+		if let Some(source) = source {
+			command
+				.env("PILLAR_SOURCE", &source.path)
+				.env("PILLAR_OUTPUT", &source.target)
+				.env("PILLAR_URL", &source.url)
+				.env("PILLAR_META", meta(source).to_string());
+			if let Some(template) = self.options.templates.get(&source.path) {
+				command.env("PILLAR_TEMPLATE", template);
+			}
+		}
+		command
+	}
+
+	// sends a worker plugin its input, starting it if it hasn't been yet
+	fn request(
+		&self,
+		plugin: &Plugin,
+		place: &str,
+		input: &str,
+		timeout: Duration,
+	) -> Result<String, PluginError> {
+		let mut workers = self.workers.borrow_mut();
+		if !workers.contains_key(&plugin.name) {
+			let worker = Worker::start(plugin, self.command(plugin, None))?;
+			workers.insert(plugin.name.clone(), worker);
+		}
+		let worker = workers.get_mut(&plugin.name).unwrap();
+		let reply = worker.request(input, place, timeout);
+		// a worker that's gone wrong is stopped, and started again if it's needed
+		if reply.is_err() {
+			workers.remove(&plugin.name);
+		}
+		reply
+	}
+
+	// runs a plugin, unless it's cached and has been run on the same input before
+	fn cached<F>(
+		&self,
+		plugin: &Plugin,
+		tag: Option<&Tag>,
+		input: &str,
+		run: F,
+	) -> Result<String, PluginError>
+	where
+		F: FnOnce() -> Result<String, PluginError>,
+	{
+		let key = self.cache.key(plugin, tag, input);
+		if let Some(output) = self.cache.get(&key) {
+			return Ok(output);
+		}
+		let output = run()?;
+		self.cache.put(&key, &output);
+		Ok(output)
+	}
+
+	fn run_legacy(
+		&self,
+		plugin: &Plugin,
+		tag: Option<&Tag>,
+		source: Option<&Source>,
+		contents: &str,
+	) -> Result<String, PluginError> {
+		let path = source.map(|s| s.path.as_str()).unwrap_or_default();
+		let input = [path, "\n", contents].concat();
+		let output = self.cached(plugin, tag, &input, || {
+			self.exec(plugin, tag, source, &input)
+		})?;
+		// takes the path back off the front
+		Ok(match output.split_once('\n') {
+			Some((_, rest)) => rest.to_string(),
+			None => String::new(),
+		})
+	}
+
+	/*
+	sends the stage, the tag, the page, its typed meta header, the resolved config and every other page as json,
+	and reads back `{ "content": "...", "warnings": ["..."] }`, where both are optional
+	plugins without tags get `null` for the tag, and site plugins get `null` for the page,
+	but can reply with `files` to write
+	*/
+	fn run_json(
+		&self,
+		plugin: &Plugin,
+		tag: Option<&Tag>,
+		source: Option<&Source>,
+		site: &Json,
+		contents: &str,
+	) -> Result<String, PluginError> {
+		let tag_json = tag.map(|tag| {
+			let named = tag
+				.named
+				.iter()
+				.map(|(k, v)| (k.clone(), Json::String(v.clone())))
+				.collect::<Map<_, _>>();
+			json!({
+				"raw": tag.raw,
+				"args": tag.args,
+				"named": named,
+			})
+		});
+		let input = json!({
+			"protocol": 2,
+			"plugin": plugin.name,
+			"stage": plugin.stage.name(),
+			"tag": tag_json,
+			"page": source.map(|s| {
+				let mut page = page(s);
+				page["template"] = json!(self.options.templates.get(&s.path));
+				page
+			}),
+			"content": contents,
+			"config": config(self.config),
+			"site": site,
+		});
+		let place = place(source);
+		// the time of the build changes every time, so it's left out of what the output is cached under
+		let mut key = input.clone();
+		key["config"]["build_time"] = Json::Null;
+		key["config"]["last_run"] = Json::Null;
+		let timeout = self.config.plugins.timeout(&plugin.name, plugin.timeout);
+		let output = self.cached(plugin, tag, &key.to_string(), || match plugin.runtime {
+			Runtime::Process => self.exec(plugin, tag, source, &input.to_string()),
+			Runtime::Rhai => self.scripts.run(plugin, &input, &place, timeout),
+			Runtime::Wasm => self
+				.modules
+				.run(plugin, &input.to_string(), &place, timeout),
+		})?;
+
+		let invalid = |message: String| PluginError {
+			plugin: plugin.name.clone(),
+			message: format!("{} on {}", message, place),
+		};
+		let reply = serde_json::from_str::<Json>(&output)
+			.map_err(|e| invalid(format!("couldn't read its reply as json: {}", e)))?;
+		let reply = reply
+			.as_object()
+			.ok_or_else(|| invalid(String::from("its reply should be a json object")))?;
+
+		if let Some(warnings) = reply.get("warnings") {
+			let warnings = warnings
+				.as_array()
+				.ok_or_else(|| invalid(String::from("`warnings` should be a list")))?;
+			for warning in warnings {
+				let warning = match warning.as_str() {
+					Some(w) => w.to_string(),
+					None => warning.to_string(),
+				};
+				println!(
+					"warning: plugin `{}` on {}: {}",
+					plugin.name, place, warning
+				);
+			}
+		}
+		match reply.get("files") {
+			None | Some(Json::Null) => (),
+			Some(Json::Object(files)) if plugin.stage == Stage::Site => {
+				self.write(plugin, files)?
+			}
+			Some(Json::Object(_)) => {
+				return Err(invalid(String::from(
+					"only site plugins can write `files`, this one runs on each page",
+				)))
+			}
+			Some(_) => return Err(invalid(String::from("`files` should be an object"))),
+		}
+		match reply.get("content") {
+			None | Some(Json::Null) => Ok(contents.to_string()),
+			Some(Json::String(content)) => Ok(content.clone()),
+			Some(_) => Err(invalid(String::from("`content` should be text"))),
+		}
+	}
+}
+
+// where a plugin ran, for messages about it
+fn place(source: Option<&Source>) -> String {
+	match source {
+		Some(source) => source.path.clone(),
+		None => String::from("the site"),
+	}
+}
+
+/*
+the different `{{tags}}` in some text, in the order they first show up
+*/
+fn tags(s: &str) -> Result<Vec<Tag>, PluginError> {
+	candidates(s)
+		.into_iter()
+		.map(|raw| {
+			Tag::parse(raw).map_err(|message| PluginError {
+				plugin: raw.to_string(),
+				message,
+			})
+		})
+		.collect()
+}
+
+/*
+everything in some text that looks like a `{{tag}}`, without repeats
+ones written as `\{{like this}}`, or in a `<pre>` or `<code>` element, aren't tags, -> escape
+*/
+pub fn candidates(s: &str) -> Vec<&str> {
+	lazy_static! {
+		static ref RE: Regex = Regex::new(r"\{\{([^\}]+)\}\}").unwrap();
+	}
+	let code = code(s);
+	let mut found = Vec::<&str>::new();
+	for m in RE.find_iter(s) {
+		let escaped = s[..m.start()].ends_with('\\') || code.iter().any(|c| c.contains(&m.start()));
+		if !escaped && !found.contains(&m.as_str()) {
+			found.push(m.as_str());
+		}
+	}
+	found
+}
+
+/*
+makes the `{{`s that aren't tags show up as they are on the built page, without looking like tags to anything after:
+`\{{like this}}` anywhere, or `{{` in a `<pre>` or `<code>` element, becomes `&#123;{`, which browsers show as `{{`
+*/
+pub fn escape(s: &str) -> String {
+	let code = code(s);
+	let mut output = String::with_capacity(s.len());
+	let mut last = 0;
+	for (at, _) in s.match_indices("{{") {
+		if s[..at].ends_with('\\') {
+			output.push_str(&s[last..at - 1]);
+		} else if code.iter().any(|c| c.contains(&at)) {
+			output.push_str(&s[last..at]);
+		} else {
+			continue;
+		}
+		output.push_str("&#123;{");
+		last = at + 2;
+	}
+	output.push_str(&s[last..]);
+	output
+}
+
+// where each `<pre>` and `<code>` element in some html is, so code examples can have `{{` in them
+fn code(s: &str) -> Vec<std::ops::Range<usize>> {
+	lazy_static! {
+		static ref OPEN: Regex = Regex::new(r"(?i)<(pre|code)[\s>]").unwrap();
+		static ref PRE: Regex = Regex::new(r"(?i)</pre\s*>").unwrap();
+		static ref CODE: Regex = Regex::new(r"(?i)</code\s*>").unwrap();
+	}
+	let mut ranges = Vec::new();
+	let mut from = 0;
+	while let Some(open) = OPEN.captures_at(s, from) {
+		let whole = open.get(0).unwrap();
+		let close = if open[1].eq_ignore_ascii_case("pre") {
+			&*PRE
+		} else {
+			&*CODE
+		};
+		// an element that's never closed goes to the end
+		let end = close.find_at(s, whole.end()).map_or(s.len(), |c| c.end());
+		ranges.push(whole.start()..end);
+		from = end;
+	}
+	ranges
+}
+
+// says which plugins added a tag, for errors about it
+fn added_by(chain: &[String]) -> String {
+	if chain.is_empty() {
+		return String::new();
+	}
+	let chain = chain
+		.iter()
+		.map(|name| format!("`{}`", name))
+		.collect::<Vec<_>>();
+	format!(" (the tag was added by {})", chain.join(" -> "))
+}
+
+// reads everything from a pipe on another thread, so it can't block the others
+fn read_all<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+	thread::spawn(move || {
+		let mut bytes = Vec::new();
+		let _ = pipe.read_to_end(&mut bytes);
+		bytes
+	})
+}
+
+// `dir` is passed as `PILLAR_ARG_DIR`, and `max-depth` as `PILLAR_ARG_MAX_DEPTH`
+fn env_name(key: &str) -> String {
+	let key = key
+		.chars()
+		.map(|c| match c {
+			'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+			_ => '_',
+		})
+		.collect::<String>();
+	format!("PILLAR_ARG_{}", key)
+}
+
+/*
+a page as json plugins see it, with its meta header typed:
+`true`/`false` are booleans, numbers are numbers, `tags` is a list and everything else is text
+*/
+fn page(source: &Source) -> Json {
+	json!({
+		"path": source.path,
+		"url": source.url,
+		"output": source.target,
+		"permalink": source.permalink,
+		"modified": source.modified,
+		"created": source.created,
+		"meta": meta(source),
+	})
+}
+
+fn meta(source: &Source) -> Json {
+	let mut meta = Map::new();
+	for m in &source.meta {
+		meta.insert(m.name.clone(), typed(&m.value));
+	}
+	meta.insert(String::from("tags"), json!(source.tags()));
+	Json::Object(meta)
+}
+
+fn typed(value: &str) -> Json {
+	if let Ok(b) = value.parse::<bool>() {
+		return Json::Bool(b);
+	}
+	if let Ok(i) = value.parse::<i64>() {
+		return json!(i);
+	}
+	match value.parse::<f64>() {
+		Ok(f) if f.is_finite() => json!(f),
+		_ => Json::String(value.to_string()),
+	}
+}
+
+// the config after pillar has read it, so plugins don't have to read .pillar.toml themselves
+fn config(config: &Config) -> Json {
+	json!({
+		"paths": {
+			"template_path": config.template_path,
+			"granite_path": config.granite_path,
+			"html_path": config.html_path,
+			"plugin_path": config.plugin_path,
+			"data_path": config.data_path,
+		},
+		"values": to_json(&Value::Table(config.values.clone())),
+		"base_url": config.base_url,
+		"last_run": config.last_run,
+		"build_time": config.build_time,
+	})
+}
+
+fn to_json(value: &Value) -> Json {
+	match value {
+		Value::String(s) => Json::String(s.clone()),
+		Value::Integer(i) => json!(i),
+		Value::Float(f) => json!(f),
+		Value::Boolean(b) => Json::Bool(*b),
+		Value::Datetime(d) => Json::String(d.to_string()),
+		Value::Array(a) => Json::Array(a.iter().map(to_json).collect()),
+		Value::Table(t) => Json::Object(t.iter().map(|(k, v)| (k.clone(), to_json(v))).collect()),
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+	use super::*;
+	use crate::config::{DateConfig, PluginConfig};
+	use std::os::unix::fs::PermissionsExt;
+
+	/*
+	a site in a temporary directory with some plugins in it, given as paths in the plugin directory
+	and what's in them, where a path ending in `/` is an empty directory
+	files are executable, so shell scripts can be run as plugins
+	*/
+	pub(crate) fn project(name: &str, files: &[(&str, &str)]) -> (PathBuf, Config) {
+		let dir = std::env::temp_dir().join(format!("pillar-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(dir.join("plugins")).unwrap();
+		for (path, contents) in files {
+			let path = dir.join("plugins").join(path);
+			if contents.is_empty() && path.to_string_lossy().ends_with('/') {
+				fs::create_dir_all(&path).unwrap();
+				continue;
+			}
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(&path, contents).unwrap();
+			fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+		}
+		let config = Config {
+			template_path: format!("{}/templates/", dir.display()),
+			granite_path: format!("{}/pages/", dir.display()),
+			html_path: format!("{}/docs/", dir.display()),
+			plugin_path: format!("{}/plugins/", dir.display()),
+			data_path: format!("{}/data/", dir.display()),
+			cache_path: format!("{}/.pillar-cache/", dir.display()),
+			last_run: 0,
+			values: toml::value::Table::new(),
+			base_url: None,
+			templates: Vec::new(),
+			plugins: PluginConfig {
+				timeout: 5,
+				timeouts: HashMap::new(),
+				rounds: 10,
+				allow: None,
+				wasm_memory: 64,
+			},
+			dates: DateConfig {
+				format: String::from("%s"),
+				utc_offset: String::from("UTC"),
+				sources: Vec::new(),
+				formats: HashMap::new(),
+			},
+			feeds: None,
+			build_time: 0,
+		};
+		(dir, config)
+	}
+
+	pub(crate) fn options(dir: &Path) -> Options {
+		Options {
+			dir: dir.to_path_buf(),
+			all: true,
+			cache: false,
+			templates: HashMap::new(),
+		}
+	}
+
+	fn tag(name: &str) -> Tag {
+		Tag {
+			raw: format!("{{{{{}}}}}", name),
+			name: name.to_string(),
+			args: Vec::new(),
+			named: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn test_typed() {
+		assert_eq!(Json::Bool(true), typed("true"));
+		assert_eq!(json!(15), typed("15"));
+		assert_eq!(json!(1.5), typed("1.5"));
+		assert_eq!(json!("Uxn Notes"), typed("Uxn Notes"));
+		assert_eq!(json!("nan"), typed("nan"));
+	}
+
+	#[test]
+	fn test_tag() {
+		let tag = Tag::parse("{{files dir: \"docs/files\", 5 'two words'}}").unwrap();
+		assert_eq!("files", tag.name);
+		assert_eq!(vec!["5", "two words"], tag.args);
+		assert_eq!(
+			vec![(String::from("dir"), String::from("docs/files"))],
+			tag.named
+		);
+		assert_eq!("PILLAR_ARG_MAX_DEPTH", env_name("max-depth"));
+
+		let tag = Tag::parse("{{latest}}").unwrap();
+		assert_eq!(("latest", 0), (tag.name.as_str(), tag.args.len()));
+		assert!(Tag::parse("{{latest \"5}}").is_err());
+		assert!(Tag::parse("{{\"latest\"}}").is_err());
+		assert!(Tag::parse("{{files dir:}}").is_err());
+	}
+
+	#[test]
+	fn test_tags() {
+		let found = tags("{{date}} {{latest 5}} {{date}} {{ latest 5 }}").unwrap();
+		let raw = found.iter().map(|t| t.raw.as_str()).collect::<Vec<_>>();
+		assert_eq!(vec!["{{date}}", "{{latest 5}}", "{{ latest 5 }}"], raw);
+
+		// code examples and escaped tags aren't tags, and are shown as they're written
+		let page =
+			"<pre>{{ user.name }}</pre> \\{{date}} <code class=\"x\">{{#each}}</code> {{latest}}";
+		assert_eq!(vec!["{{latest}}"], candidates(page));
+		assert_eq!(
+			"<pre>&#123;{ user.name }}</pre> &#123;{date}} <code class=\"x\">&#123;{#each}}</code> {{latest}}",
+			escape(page)
+		);
+		assert_eq!(vec!["{{latest}}"], candidates(&escape(page)));
+		assert!(candidates("<PRE>{{ never closed").is_empty());
+		assert_eq!("", added_by(&[]));
+		assert_eq!(
+			" (the tag was added by `a` -> `b`)",
+			added_by(&[String::from("a"), String::from("b")])
+		);
+	}
+
+	#[test]
+	fn test_handler() {
+		let (dir, mut config) = project(
+			"handler",
+			&[
+				("title", "#!/bin/sh\ncat\n"),
+				("lib/", ""),
+				(".hidden", "#!/bin/sh\ncat\n"),
+			],
+		);
+		let plugins = Plugins::new(&config, &[], options(&dir)).unwrap();
+		let found = plugins.handler(Stage::PostTemplate, &tag("title")).unwrap();
+		assert_eq!("title", found.unwrap().name);
+		// before templating, tags no plugin handles are left for later
+		assert!(plugins
+			.handler(Stage::PreParse, &tag("missing"))
+			.unwrap()
+			.is_none());
+
+		for name in &["../x", "a/b", "a\\b", ".hidden"] {
+			let error = plugins
+				.handler(Stage::PostTemplate, &tag(name))
+				.unwrap_err();
+			assert!(error.message.contains("can't have"), "{}", error.message);
+		}
+		// a directory without a plugin.toml isn't a plugin, and neither is something that isn't there
+		for name in &["lib", "missing"] {
+			let error = plugins
+				.handler(Stage::PostTemplate, &tag(name))
+				.unwrap_err();
+			assert!(
+				error.message.contains("there is no plugin"),
+				"{}",
+				error.message
+			);
+		}
+		drop(plugins);
+
+		config.plugins.allow = Some(vec![String::from("other")]);
+		let plugins = Plugins::new(&config, &[], options(&dir)).unwrap();
+		let error = plugins
+			.handler(Stage::PostTemplate, &tag("title"))
+			.unwrap_err();
+		assert!(error.message.contains("allow list"), "{}", error.message);
+		drop(plugins);
+
+		config.plugins.allow = Some(vec![String::from("title")]);
+		let plugins = Plugins::new(&config, &[], options(&dir)).unwrap();
+		assert!(plugins.handler(Stage::PostTemplate, &tag("title")).is_ok());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_exec() {
+		let (dir, mut config) = project(
+			"exec",
+			&[
+				("ok", "#!/bin/sh\necho starting >&2\ncat\n"),
+				("fails", "#!/bin/sh\necho 'no such page' >&2\nexit 3\n"),
+				("slow", "#!/bin/sh\nexec sleep 10\n"),
+			],
+		);
+		config.plugins.timeouts.insert(String::from("slow"), 1);
+		let plugins = Plugins::new(&config, &[], options(&dir)).unwrap();
+		let run = |name: &str| {
+			let plugin = plugins.plugins.iter().find(|p| p.name == name).unwrap();
+			plugins.exec(plugin, None, None, "some input")
+		};
+
+		assert_eq!("some input", run("ok").unwrap());
+		let error = run("fails").unwrap_err();
+		assert_eq!(
+			"exited with status 3 on the site:\nno such page",
+			error.message
+		);
+		let start = Instant::now();
+		let error = run("slow").unwrap_err();
+		assert_eq!("was stopped after 1 seconds on the site", error.message);
+		assert!(start.elapsed() < Duration::from_secs(5));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_env() {
+		let (dir, config) = project(
+			"env",
+			&[(
+				"env",
+				"#!/bin/sh\nprintenv PILLAR_ARG_DIR PILLAR_SOURCE PILLAR_OUTPUT PILLAR_TEMPLATE PILLAR_TAG\necho \"$@\"\n",
+			)],
+		);
+		let source = Source {
+			path: String::from("pages/notes/uxn.gn"),
+			target: String::from("docs/notes/uxn.html"),
+			url: String::from("notes/uxn.html"),
+			meta: Vec::new(),
+			modified: 0,
+			created: 0,
+			permalink: String::from("notes/uxn.html"),
+		};
+		let mut options = options(&dir);
+		options
+			.templates
+			.insert(source.path.clone(), String::from("note"));
+		let plugins = Plugins::new(&config, &[], options).unwrap();
+		let tag = Tag::parse("{{env dir: \"docs/files\" 5}}").unwrap();
+		let plugin = plugins.handler(Stage::PostTemplate, &tag).unwrap().unwrap();
+
+		assert_eq!(
+			"docs/files\npages/notes/uxn.gn\ndocs/notes/uxn.html\nnote\n{{env dir: \"docs/files\" 5}}\n5\n",
+			plugins.exec(plugin, Some(&tag), Some(&source), "").unwrap()
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,148 @@
+use super::{Plugin, PluginError, Tag};
+use crate::utils::glob;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+/*
+what plugins with `cache = true` in their plugin.toml gave back for each input, so they aren't run again for it
+an output is kept under a hash of the plugin's name, its file, the tag, its input
+and every file matching its `inputs` globs, so changing any of them runs it again
+it's kept in the cache directory (`.pillar-cache/` unless [paths] says otherwise), one file per output
+*/
+pub struct Cache {
+	// `None` when the cache is turned off with `--no-plugin-cache`
+	dir: Option<PathBuf>,
+	// the hash of each cached plugin's file and inputs, which don't change during a build
+	plugins: HashMap<String, String>,
+}
+
+impl Cache {
+	pub fn new(dir: &str, enabled: bool, plugins: &[Plugin]) -> Result<Cache, PluginError> {
+		if !enabled {
+			return Ok(Cache {
+				dir: None,
+				plugins: HashMap::new(),
+			});
+		}
+		let mut hashes = HashMap::new();
+		for plugin in plugins.iter().filter(|p| p.cache) {
+			hashes.insert(plugin.name.clone(), files(plugin, dir)?);
+		}
+		Ok(Cache {
+			dir: Some(PathBuf::from(dir)),
+			plugins: hashes,
+		})
+	}
+
+	/*
+	where a plugin's output for an input is kept, or `None` if it isn't cached
+	*/
+	pub fn key(&self, plugin: &Plugin, tag: Option<&Tag>, input: &str) -> Option<PathBuf> {
+		let dir = self.dir.as_ref()?;
+		let files = self.plugins.get(&plugin.name)?;
+		let mut hash = Sha256::new();
+		for part in &[
+			plugin.name.as_str(),
+			files,
+			tag.map(|t| t.raw.as_str()).unwrap_or_default(),
+			input,
+		] {
+			// each part's length goes first, so moving text from one to the next changes the hash
+			hash.update((part.len() as u64).to_le_bytes());
+			hash.update(part.as_bytes());
+		}
+		Some(dir.join(hex(&hash.finalize())))
+	}
+
+	pub fn get(&self, key: &Option<PathBuf>) -> Option<String> {
+		fs::read_to_string(key.as_ref()?).ok()
+	}
+
+	// a cache that can't be written to just means the plugin runs again next time
+	pub fn put(&self, key: &Option<PathBuf>, output: &str) {
+		if let (Some(dir), Some(key)) = (&self.dir, key) {
+			let _ = fs::create_dir_all(dir).and_then(|_| fs::write(key, output));
+		}
+	}
+}
+
+/*
+hashes a plugin's file and every file in the project matching its `inputs`,
+except for the cache itself and hidden files
+*/
+fn files(plugin: &Plugin, cache: &str) -> Result<String, PluginError> {
+	let error = |path: &str, e: std::io::Error| PluginError {
+		plugin: plugin.name.clone(),
+		message: format!("couldn't read {} to cache it: {}", path, e),
+	};
+	let mut hash = Sha256::new();
+	let run = plugin.run.to_string_lossy();
+	hash.update(fs::read(&plugin.run).map_err(|e| error(&run, e))?);
+
+	let mut paths = Vec::new();
+	if !plugin.inputs.is_empty() {
+		let cache = cache.trim_end_matches('/');
+		let walk = WalkDir::new(".").into_iter().filter_entry(|e| {
+			let name = e.file_name().to_string_lossy();
+			e.depth() == 0
+				|| !(name.starts_with('.')
+					|| e.path().strip_prefix(".").ok() == Some(cache.as_ref()))
+		});
+		for entry in walk
+			.filter_map(|e| e.ok())
+			.filter(|e| e.file_type().is_file())
+		{
+			let path = entry.path().strip_prefix(".").unwrap_or(entry.path());
+			let path = path.to_string_lossy().to_string();
+			if plugin.inputs.iter().any(|i| glob::matches(i, &path)) {
+				paths.push(path);
+			}
+		}
+	}
+	paths.sort();
+	for path in paths {
+		hash.update((path.len() as u64).to_le_bytes());
+		hash.update(path.as_bytes());
+		let contents = fs::read(&path).map_err(|e| error(&path, e))?;
+		hash.update((contents.len() as u64).to_le_bytes());
+		hash.update(contents);
+	}
+	Ok(hex(&hash.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_key() {
+		let mut plugin = Plugin::single(String::from("date"), PathBuf::from("date"));
+		plugin.cache = true;
+		let mut plugins = HashMap::new();
+		plugins.insert(String::from("date"), String::from("files"));
+		let cache = Cache {
+			dir: Some(PathBuf::from(".pillar-cache/")),
+			plugins,
+		};
+		let tag = Tag::parse("{{date}}").unwrap();
+
+		let key = cache.key(&plugin, Some(&tag), "input").unwrap();
+		assert!(key.starts_with(".pillar-cache/"));
+		assert_eq!(Some(key.clone()), cache.key(&plugin, Some(&tag), "input"));
+		assert_ne!(
+			Some(key.clone()),
+			cache.key(&plugin, Some(&tag), "other input")
+		);
+		assert_ne!(Some(key), cache.key(&plugin, None, "input"));
+
+		plugin.name = String::from("latest");
+		assert_eq!(None, cache.key(&plugin, Some(&tag), "input"));
+	}
+}
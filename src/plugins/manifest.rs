@@ -0,0 +1,376 @@
+use super::PluginError;
+use crate::pipeline::Stage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/*
+how a plugin wants to be talked to
+legacy plugins get the page's path on the first line of stdin and the html after it, and give both back,
+json plugins (ones with `pillar-protocol: 2` near the top of the file) get a json object and reply with one
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+	Legacy,
+	Json,
+}
+
+/*
+what runs a plugin: a program pillar starts, or a `.rhai` script or `.wasm` module it runs itself,
+-> plugins/script.rs and plugins/wasm.rs
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Runtime {
+	Process,
+	Rhai,
+	Wasm,
+}
+
+impl Runtime {
+	fn of(run: &Path) -> Runtime {
+		match run.extension().and_then(|e| e.to_str()) {
+			Some("rhai") => Runtime::Rhai,
+			Some("wasm") => Runtime::Wasm,
+			_ => Runtime::Process,
+		}
+	}
+}
+
+/*
+when in a build a plugin runs:
+on a page's granite before it's parsed, on the html parsed from it, on the whole page once it's been templated,
+or once for the whole site after every page is built
+*/
+fn stage(name: &str) -> Option<Stage> {
+	Stage::PLUGINS.iter().find(|s| s.name() == name).copied()
+}
+
+/*
+a plugin, either a single executable file, `.rhai` script or `.wasm` module in the plugin directory,
+or a directory with a `plugin.toml` manifest saying how to run it:
+```toml
+run = "feed.py"          # the file to run, in the plugin's directory
+stage = "post-template"  # or "pre-parse", "post-parse" or "site"
+tags = ["feed", "rss"]   # the tags it replaces, or [] to run it on every page
+after = ["title"]        # plugins it has to run after, or `before`
+protocol = "json"        # or "legacy", found from the file if it isn't given
+timeout = 60
+worker = true            # started once and sent every request, -> plugins/worker.rs
+cache = true             # reuse its output for the same input, -> plugins/cache.rs
+inputs = ["links.toml"]  # files its output depends on besides its input, as globs
+```
+a plugin without a manifest runs after templating, for tags with its name (without `.rhai` or `.wasm`)
+*/
+#[derive(Debug)]
+pub struct Plugin {
+	pub name: String,
+	pub run: PathBuf,
+	pub runtime: Runtime,
+	pub stage: Stage,
+	pub tags: Vec<String>,
+	pub protocol: Protocol,
+	pub after: Vec<String>,
+	pub before: Vec<String>,
+	pub timeout: Option<u64>,
+	pub worker: bool,
+	pub cache: bool,
+	pub inputs: Vec<String>,
+}
+
+impl Plugin {
+	// a plugin with no tags is run on every page
+	pub fn every_page(&self) -> bool {
+		self.tags.is_empty()
+	}
+
+	pub fn handles(&self, tag: &str) -> bool {
+		self.tags.iter().any(|t| t == tag)
+	}
+
+	pub(super) fn single(name: String, run: PathBuf) -> Plugin {
+		let runtime = Runtime::of(&run);
+		// scripts and modules are given the same things json plugins are
+		let protocol = match runtime {
+			Runtime::Rhai | Runtime::Wasm => Protocol::Json,
+			Runtime::Process => detect(&run),
+		};
+		Plugin {
+			tags: vec![name.clone()],
+			name,
+			run,
+			runtime,
+			stage: Stage::PostTemplate,
+			protocol,
+			after: Vec::new(),
+			before: Vec::new(),
+			timeout: None,
+			worker: false,
+			cache: false,
+			inputs: Vec::new(),
+		}
+	}
+
+	fn from_manifest(name: String, dir: &Path) -> Result<Plugin, PluginError> {
+		let path = dir.join("plugin.toml");
+		let error = |message: String| PluginError {
+			plugin: name.clone(),
+			message: format!("{}: {}", path.display(), message),
+		};
+		let manifest = fs::read_to_string(&path)
+			.map_err(|e| error(e.to_string()))?
+			.parse::<Value>()
+			.map_err(|e| error(e.to_string()))?;
+
+		let text = |key: &str| -> Result<Option<String>, PluginError> {
+			match manifest.get(key) {
+				None => Ok(None),
+				Some(Value::String(s)) => Ok(Some(s.clone())),
+				Some(_) => Err(error(format!("`{}` should be text", key))),
+			}
+		};
+		let list = |key: &str| -> Result<Option<Vec<String>>, PluginError> {
+			match manifest.get(key) {
+				None => Ok(None),
+				Some(Value::Array(a)) => a
+					.iter()
+					.map(|v| match v {
+						Value::String(s) => Ok(s.clone()),
+						_ => Err(error(format!("`{}` should be a list of text", key))),
+					})
+					.collect::<Result<Vec<_>, _>>()
+					.map(Some),
+				Some(_) => Err(error(format!("`{}` should be a list of text", key))),
+			}
+		};
+
+		let run = dir.join(text("run")?.unwrap_or_else(|| name.clone()));
+		if !run.is_file() {
+			return Err(error(format!("there is no {} to run", run.display())));
+		}
+		let stage = match text("stage")? {
+			None => Stage::PostTemplate,
+			Some(s) => stage(&s).ok_or_else(|| {
+				error(format!(
+					"`{}` isn't a stage, it should be pre-parse, post-parse, post-template or site",
+					s
+				))
+			})?,
+		};
+		let runtime = Runtime::of(&run);
+		let protocol = match text("protocol")?.as_deref() {
+			None if stage == Stage::Site || runtime != Runtime::Process => Protocol::Json,
+			None => detect(&run),
+			Some("json") => Protocol::Json,
+			Some("legacy") if stage == Stage::Site => return Err(error(String::from(
+				"site plugins have no page to give a legacy plugin, so they use the json protocol",
+			))),
+			Some("legacy") => Protocol::Legacy,
+			Some(other) => {
+				return Err(error(format!(
+					"`{}` isn't a protocol, it should be json or legacy",
+					other
+				)))
+			}
+		};
+		let timeout = match manifest.get("timeout") {
+			None => None,
+			Some(Value::Integer(i)) if *i > 0 => Some(*i as u64),
+			Some(_) => {
+				return Err(error(String::from(
+					"`timeout` should be a number of seconds",
+				)))
+			}
+		};
+
+		let flag = |key: &str| match manifest.get(key) {
+			None => Ok(false),
+			Some(Value::Boolean(b)) => Ok(*b),
+			Some(_) => Err(error(format!("`{}` should be true or false", key))),
+		};
+		let worker = flag("worker")?;
+		if runtime != Runtime::Process && (worker || protocol == Protocol::Legacy) {
+			return Err(error(String::from(
+				"scripts and modules are run inside pillar with what json plugins get, so they can't be workers or legacy plugins",
+			)));
+		}
+		// a running worker can't be given a tag's arguments on its command line
+		if worker && protocol == Protocol::Legacy {
+			return Err(error(String::from(
+				"workers have to use the json protocol, since that's how they're given each tag",
+			)));
+		}
+
+		Ok(Plugin {
+			tags: list("tags")?.unwrap_or_else(|| vec![name.clone()]),
+			run,
+			runtime,
+			stage,
+			protocol,
+			after: list("after")?.unwrap_or_default(),
+			before: list("before")?.unwrap_or_default(),
+			timeout,
+			worker,
+			cache: flag("cache")?,
+			inputs: list("inputs")?.unwrap_or_default(),
+			name,
+		})
+	}
+}
+
+/*
+a plugin file is a json plugin if `pillar-protocol: 2` is in its first few lines,
+usually as a comment under the shebang
+*/
+fn detect(run: &Path) -> Protocol {
+	let head = fs::read(run)
+		.map(|bytes| {
+			let end = bytes.len().min(1024);
+			String::from_utf8_lossy(&bytes[..end]).to_string()
+		})
+		.unwrap_or_default();
+	if head
+		.lines()
+		.take(5)
+		.any(|l| l.contains("pillar-protocol: 2"))
+	{
+		Protocol::Json
+	} else {
+		Protocol::Legacy
+	}
+}
+
+/*
+finds every plugin in the plugin directory, in the order they should run
+hidden files and directories without a `plugin.toml` are skipped
+*/
+pub fn discover(dir: &str) -> Result<Vec<Plugin>, PluginError> {
+	let mut entries = match fs::read_dir(dir) {
+		Ok(entries) => entries.filter_map(|e| e.ok()).collect::<Vec<_>>(),
+		Err(_) => return Ok(Vec::new()),
+	};
+	entries.sort_by_key(|e| e.file_name());
+
+	let mut plugins = Vec::new();
+	for entry in entries {
+		let name = match entry.file_name().to_str() {
+			Some(name) if !name.starts_with('.') => name.to_string(),
+			_ => continue,
+		};
+		let path = entry.path();
+		if path.is_dir() {
+			if path.join("plugin.toml").is_file() {
+				plugins.push(Plugin::from_manifest(name, &path)?);
+			}
+		} else if path.is_file() {
+			let name = match Runtime::of(&path) {
+				Runtime::Rhai => name.trim_end_matches(".rhai").to_string(),
+				Runtime::Wasm => name.trim_end_matches(".wasm").to_string(),
+				Runtime::Process => name,
+			};
+			plugins.push(Plugin::single(name, path));
+		}
+	}
+	for (i, plugin) in plugins.iter().enumerate() {
+		if plugins[..i].iter().any(|p| p.name == plugin.name) {
+			return Err(PluginError {
+				plugin: plugin.name.clone(),
+				message: format!(
+					"there's more than one plugin named `{}` in {}",
+					plugin.name, dir
+				),
+			});
+		}
+	}
+	order(plugins)
+}
+
+/*
+puts plugins in an order that keeps to every `after` and `before`, and is alphabetical otherwise
+plugins named in `after` or `before` that aren't there are ignored
+*/
+fn order(mut plugins: Vec<Plugin>) -> Result<Vec<Plugin>, PluginError> {
+	let index = |name: &str, plugins: &[Plugin]| plugins.iter().position(|p| p.name == name);
+	// `needs[i]` has the plugins which have to run before plugin i
+	let mut needs = vec![Vec::<usize>::new(); plugins.len()];
+	for (i, plugin) in plugins.iter().enumerate() {
+		for name in &plugin.after {
+			if let Some(j) = index(name, &plugins) {
+				needs[i].push(j);
+			}
+		}
+		for name in &plugin.before {
+			if let Some(j) = index(name, &plugins) {
+				needs[j].push(i);
+			}
+		}
+	}
+
+	let mut ordered = Vec::<usize>::new();
+	while ordered.len() < plugins.len() {
+		let next = (0..plugins.len())
+			.find(|i| !ordered.contains(i) && needs[*i].iter().all(|j| ordered.contains(j)));
+		match next {
+			Some(i) => ordered.push(i),
+			None => {
+				let stuck = (0..plugins.len())
+					.filter(|i| !ordered.contains(i))
+					.map(|i| format!("`{}`", plugins[i].name))
+					.collect::<Vec<_>>();
+				return Err(PluginError {
+					plugin: plugins[(0..plugins.len()).find(|i| !ordered.contains(i)).unwrap()]
+						.name
+						.clone(),
+					message: format!(
+						"{} have to run before each other, so they can't run at all",
+						stuck.join(", ")
+					),
+				});
+			}
+		}
+	}
+
+	let mut slots = plugins.drain(..).map(Some).collect::<Vec<_>>();
+	Ok(ordered
+		.into_iter()
+		.map(|i| slots[i].take().unwrap())
+		.collect())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn plugin(name: &str, after: &[&str], before: &[&str]) -> Plugin {
+		let mut plugin = Plugin::single(name.to_string(), PathBuf::from(name));
+		plugin.after = after.iter().map(|s| s.to_string()).collect();
+		plugin.before = before.iter().map(|s| s.to_string()).collect();
+		plugin
+	}
+
+	#[test]
+	fn test_order() {
+		let plugins = vec![
+			plugin("a", &["c"], &[]),
+			plugin("b", &[], &[]),
+			plugin("c", &[], &[]),
+			plugin("d", &[], &["a", "missing"]),
+		];
+		let names = order(plugins)
+			.unwrap()
+			.into_iter()
+			.map(|p| p.name)
+			.collect::<Vec<_>>();
+		assert_eq!(vec!["b", "c", "d", "a"], names);
+
+		let plugins = vec![plugin("a", &["b"], &[]), plugin("b", &["a"], &[])];
+		assert!(order(plugins).is_err());
+	}
+
+	#[test]
+	fn test_stage() {
+		assert_eq!(Some(Stage::PreParse), stage("pre-parse"));
+		assert_eq!(None, stage("later"));
+		assert_eq!(None, stage("template"));
+		assert_eq!("post-template", Stage::PostTemplate.to_string());
+	}
+}
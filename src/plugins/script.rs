@@ -0,0 +1,344 @@
+use super::{Plugin, PluginError};
+use crate::granite::{self, Node};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde_json::{json, Value as Json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/*
+runs `.rhai` plugins inside pillar, so there's no interpreter to start for each tag
+a script is given the same things a json plugin is, as variables:
+`tag`, `page`, `content`, `config`, `site`, `stage` and `plugin`,
+and gives back the new content, or a map like a json plugin's reply:
+```rhai
+let words = content.split(" ").len();
+content.replace(tag.raw, `${words} words`);
+content
+```
+scripts can't touch anything outside pillar except through these functions:
+`read_file(path)` reads a file in the project, `granite(text)` parses granite into html,
+`warn(message)` prints a warning with the page's name, and `print` does the same without the warning
+
+once a page is html, after parsing, scripts also get its structure as `nodes`,
+a list where each element is a map like `#{ tag: "a", attrs: #{ href: "uxn.html" }, children: [...] }`
+and each piece of text is `#{ text: "..." }`, -> granite.rs:[nodes(html: &str)]
+`granite_nodes(text)` gives the same for some granite, and `render(nodes)` writes nodes back out as html:
+```rhai
+for i in 0..nodes.len() {
+	if nodes[i].tag == "h1" { nodes[i].attrs.id = "title"; }
+}
+render(nodes)
+```
+*/
+pub struct Scripts {
+	engine: Engine,
+	// each script, parsed the first time it's run
+	parsed: RefCell<HashMap<String, AST>>,
+	running: Rc<RefCell<Running>>,
+}
+
+// the script that's running, which the engine's callbacks need to know about
+struct Running {
+	plugin: String,
+	place: String,
+	deadline: Instant,
+	warnings: Vec<String>,
+}
+
+impl Scripts {
+	// `dir` is the project directory, which is the only place scripts can read files from
+	pub fn new(dir: &Path) -> Scripts {
+		let running = Rc::new(RefCell::new(Running {
+			plugin: String::new(),
+			place: String::new(),
+			deadline: Instant::now(),
+			warnings: Vec::new(),
+		}));
+		let mut engine = Engine::new();
+		// `import` would read other scripts from anywhere
+		engine.set_module_resolver(DummyModuleResolver::new());
+		engine.set_max_call_levels(64);
+
+		let timer = running.clone();
+		engine.on_progress(move |operations| {
+			// checking the time on every operation would slow scripts down more than it's worth
+			if operations % 1024 == 0 && Instant::now() > timer.borrow().deadline {
+				Some(Dynamic::UNIT)
+			} else {
+				None
+			}
+		});
+		let printer = running.clone();
+		engine.on_print(move |line| {
+			let running = printer.borrow();
+			println!("[{} on {}] {}", running.plugin, running.place, line);
+		});
+		let warner = running.clone();
+		engine.register_fn("warn", move |message: &str| {
+			warner.borrow_mut().warnings.push(message.to_string());
+		});
+
+		let root = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+		engine.register_fn(
+			"read_file",
+			move |path: &str| -> Result<String, Box<EvalAltResult>> {
+				let file = inside(&root, path)
+					.ok_or_else(|| format!("can't read `{}`, it's outside the project", path))?;
+				fs::read_to_string(&file)
+					.map_err(|e| format!("couldn't read `{}`: {}", path, e).into())
+			},
+		);
+		engine.register_fn("granite", |text: &str| granite::parse_granite(text));
+		engine.register_fn("granite_nodes", |text: &str| {
+			to_array(&granite::nodes(&granite::parse_granite(text)))
+		});
+		engine.register_fn(
+			"render",
+			|nodes: Array| -> Result<String, Box<EvalAltResult>> {
+				Ok(granite::render(&from_array(&nodes)?))
+			},
+		);
+
+		Scripts {
+			engine,
+			parsed: RefCell::new(HashMap::new()),
+			running,
+		}
+	}
+
+	/*
+	runs a script with what a json plugin would be given,
+	and returns its reply as json, so it's handled the same way as one
+	*/
+	pub fn run(
+		&self,
+		plugin: &Plugin,
+		input: &Json,
+		place: &str,
+		timeout: Duration,
+	) -> Result<String, PluginError> {
+		let error = |message: String| PluginError {
+			plugin: plugin.name.clone(),
+			message,
+		};
+		if !self.parsed.borrow().contains_key(&plugin.name) {
+			let script = fs::read_to_string(&plugin.run)
+				.map_err(|e| error(format!("couldn't read {}: {}", plugin.run.display(), e)))?;
+			let ast = self
+				.engine
+				.compile(&script)
+				.map_err(|e| error(format!("{} in {}", e, plugin.run.display())))?;
+			self.parsed.borrow_mut().insert(plugin.name.clone(), ast);
+		}
+		let parsed = self.parsed.borrow();
+		let ast = &parsed[&plugin.name];
+
+		let mut scope = Scope::new();
+		if let Some(input) = input.as_object() {
+			for (name, value) in input {
+				let value = to_dynamic(value).map_err(|e| error(e.to_string()))?;
+				scope.push_dynamic(name.as_str(), value);
+			}
+		}
+		// pre-parse scripts get granite, which has to be parsed first
+		let html = input["stage"].as_str().is_some_and(|s| s != "pre-parse");
+		if let (true, Some(content)) = (html, input["content"].as_str()) {
+			scope.push("nodes", to_array(&granite::nodes(content)));
+		}
+		*self.running.borrow_mut() = Running {
+			plugin: plugin.name.clone(),
+			place: place.to_string(),
+			deadline: Instant::now() + timeout,
+			warnings: Vec::new(),
+		};
+
+		let result = self
+			.engine
+			.eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+			.map_err(|e| match *e {
+				EvalAltResult::ErrorTerminated(..) => error(format!(
+					"was stopped after {} seconds on {}",
+					timeout.as_secs(),
+					place
+				)),
+				e => error(format!("{} on {}", e, place)),
+			})?;
+		let mut reply = if result.is_unit() {
+			json!({})
+		} else if result.is_string() {
+			json!({ "content": result.into_string().unwrap_or_default() })
+		} else {
+			from_dynamic::<Json>(&result).map_err(|e| error(format!("{} on {}", e, place)))?
+		};
+
+		// warnings from `warn` go with any the script gave back
+		let warnings = std::mem::take(&mut self.running.borrow_mut().warnings);
+		if let (Some(reply), false) = (reply.as_object_mut(), warnings.is_empty()) {
+			let mut all = match reply.remove("warnings") {
+				Some(Json::Array(given)) => given,
+				Some(other) => vec![other],
+				None => Vec::new(),
+			};
+			all.extend(warnings.into_iter().map(Json::String));
+			reply.insert(String::from("warnings"), Json::Array(all));
+		}
+		Ok(reply.to_string())
+	}
+}
+
+// nodes as rhai maps, -> Scripts
+fn to_array(nodes: &[Node]) -> Array {
+	nodes
+		.iter()
+		.map(|node| {
+			let mut map = Map::new();
+			match node {
+				Node::Text(text) => {
+					map.insert("text".into(), text.clone().into());
+				}
+				Node::Element {
+					tag,
+					attrs,
+					children,
+				} => {
+					let attrs = attrs
+						.iter()
+						.map(|(name, value)| (name.into(), value.clone().into()))
+						.collect::<Map>();
+					map.insert("tag".into(), tag.clone().into());
+					map.insert("attrs".into(), attrs.into());
+					map.insert("children".into(), to_array(children).into());
+				}
+			}
+			map.into()
+		})
+		.collect()
+}
+
+// rhai maps back into nodes, which need a `text` or a `tag`
+fn from_array(nodes: &Array) -> Result<Vec<Node>, String> {
+	let text = |v: &Dynamic| v.clone().into_string().ok();
+	let mut read = Vec::new();
+	for node in nodes {
+		let map = node
+			.read_lock::<Map>()
+			.ok_or_else(|| format!("`{}` isn't a node, it should be a map", node))?;
+		if let Some(t) = map.get("text").and_then(text) {
+			read.push(Node::Text(t));
+			continue;
+		}
+		let tag = map
+			.get("tag")
+			.and_then(text)
+			.ok_or_else(|| String::from("a node needs a `text` or a `tag`"))?;
+		let attrs = match map.get("attrs").and_then(|a| a.read_lock::<Map>()) {
+			Some(attrs) => attrs
+				.iter()
+				.map(|(name, value)| (name.to_string(), value.to_string()))
+				.collect(),
+			None => Vec::new(),
+		};
+		let children = match map.get("children").and_then(|c| c.read_lock::<Array>()) {
+			Some(children) => from_array(&children)?,
+			None => Vec::new(),
+		};
+		read.push(Node::Element {
+			tag,
+			attrs,
+			children,
+		});
+	}
+	Ok(read)
+}
+
+// the file at `path` in the project, if it is in the project
+fn inside(root: &Path, path: &str) -> Option<PathBuf> {
+	let file = root.join(path).canonicalize().ok()?;
+	if file.starts_with(root) {
+		Some(file)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::pipeline::Stage;
+
+	#[test]
+	fn test_script() {
+		let dir = std::env::temp_dir().join(format!("pillar-script-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let run = dir.join("count.rhai");
+		fs::write(
+			&run,
+			"warn(\"counting\"); content.replace(tag.raw, `${content.split(\" \").len()} words`); content",
+		)
+		.unwrap();
+		let mut plugin = Plugin::single(String::from("count"), run);
+		plugin.stage = Stage::PostTemplate;
+
+		let scripts = Scripts::new(&dir);
+		let input = json!({ "tag": { "raw": "{{count}}" }, "content": "three words {{count}}" });
+		let reply = scripts
+			.run(&plugin, &input, "test.gn", Duration::from_secs(5))
+			.unwrap();
+		assert_eq!(
+			json!({ "content": "three words 3 words", "warnings": ["counting"] }),
+			serde_json::from_str::<Json>(&reply).unwrap()
+		);
+
+		// walks the page's nodes, and changes one before writing them back out
+		fs::write(
+			&plugin.run,
+			r#"
+			fn links(nodes) {
+				let found = [];
+				for node in nodes {
+					if node.tag == "a" { found.push(node.attrs.href); }
+					if node.children != () { found += links(node.children); }
+				}
+				found
+			}
+			let links = links(nodes);
+			nodes[0].attrs.class = "first";
+			#{ content: render(nodes), warnings: links }
+			"#,
+		)
+		.unwrap();
+		let scripts = Scripts::new(&dir);
+		let input = json!({
+			"stage": "post-parse",
+			"content": "<p>see <a href=\"uxn.html\">uxn</a></p><ul><li><a href='orca.html'>orca</a></li></ul>"
+		});
+		let reply = scripts
+			.run(&plugin, &input, "test.gn", Duration::from_secs(5))
+			.unwrap();
+		assert_eq!(
+			json!({
+				"content": "<p class=\"first\">see <a href=\"uxn.html\">uxn</a></p><ul><li><a href=\"orca.html\">orca</a></li></ul>",
+				"warnings": ["uxn.html", "orca.html"]
+			}),
+			serde_json::from_str::<Json>(&reply).unwrap()
+		);
+
+		fs::write(&plugin.run, "read_file(\"../../etc/passwd\")").unwrap();
+		let scripts = Scripts::new(&dir);
+		assert!(scripts
+			.run(&plugin, &input, "test.gn", Duration::from_secs(5))
+			.is_err());
+
+		fs::write(&plugin.run, "loop {}").unwrap();
+		let scripts = Scripts::new(&dir);
+		let stopped = scripts.run(&plugin, &input, "test.gn", Duration::from_millis(50));
+		assert!(stopped.unwrap_err().message.starts_with("was stopped"));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
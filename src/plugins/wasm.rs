@@ -0,0 +1,448 @@
+use super::{Plugin, PluginError};
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wasmi::core::{TrapCode, ValType};
+use wasmi::{
+	Caller, Config, Engine, Error, Extern, ExternType, Linker, Memory, Module, Store, StoreLimits,
+	StoreLimitsBuilder, Val,
+};
+
+// about how many instructions a module runs in a second, which its timeout is turned into
+const FUEL_PER_SECOND: u64 = 100_000_000;
+
+// wasi's error numbers for the calls modules can make
+const SUCCESS: i32 = 0;
+const BAD_FILE: i32 = 8;
+const BAD_ADDRESS: i32 = 21;
+const NOT_AVAILABLE: i32 = 52;
+
+/*
+runs `.wasm` plugins inside pillar, with no access to anything but what they're given
+a module exports its `memory` and two functions, and is given the same json a json plugin is:
+```
+pillar_alloc(length: i32) -> i32         // somewhere to put `length` bytes of input
+pillar_run(input: i32, length: i32) -> i64  // the reply's address and length, as (address << 32) | length
+```
+the reply is the same as a json plugin's, like `{"content": "..."}`
+modules can use wasi to print to stdout and stderr, read the clock and get random numbers,
+but there are no files, environment variables or arguments, and every other call fails
+each run gets a fresh instance, which is stopped when it runs out of fuel for its timeout,
+or can't grow its memory past `wasm_memory` megabytes from [plugins]
+*/
+pub struct Modules {
+	engine: Engine,
+	// each module, compiled the first time it's run
+	compiled: RefCell<HashMap<String, Module>>,
+	// how many bytes of memory a module can have
+	memory: usize,
+}
+
+// what a running module's calls back into pillar need
+struct Host {
+	plugin: String,
+	place: String,
+	limits: StoreLimits,
+	// what the module has printed since its last full line
+	printed: Vec<u8>,
+}
+
+impl Modules {
+	pub fn new(megabytes: usize) -> Modules {
+		let mut config = Config::default();
+		config.consume_fuel(true);
+		Modules {
+			engine: Engine::new(&config),
+			compiled: RefCell::new(HashMap::new()),
+			memory: megabytes * 1024 * 1024,
+		}
+	}
+
+	/*
+	runs a module with what a json plugin would be given, and returns its reply
+	*/
+	pub fn run(
+		&self,
+		plugin: &Plugin,
+		input: &str,
+		place: &str,
+		timeout: Duration,
+	) -> Result<String, PluginError> {
+		let error = |message: String| PluginError {
+			plugin: plugin.name.clone(),
+			message,
+		};
+		if !self.compiled.borrow().contains_key(&plugin.name) {
+			let bytes = fs::read(&plugin.run)
+				.map_err(|e| error(format!("couldn't read {}: {}", plugin.run.display(), e)))?;
+			let module = Module::new(&self.engine, &bytes[..]).map_err(|e| {
+				error(format!(
+					"{} isn't a webassembly module: {}",
+					plugin.run.display(),
+					e
+				))
+			})?;
+			self.compiled
+				.borrow_mut()
+				.insert(plugin.name.clone(), module);
+		}
+		let compiled = self.compiled.borrow();
+		let module = &compiled[&plugin.name];
+
+		let host = Host {
+			plugin: plugin.name.clone(),
+			place: place.to_string(),
+			limits: StoreLimitsBuilder::new()
+				.memory_size(self.memory)
+				.instances(1)
+				.build(),
+			printed: Vec::new(),
+		};
+		let mut store = Store::new(&self.engine, host);
+		store.limiter(|host| &mut host.limits);
+		let fuel = timeout.as_secs().max(1).saturating_mul(FUEL_PER_SECOND);
+		store.set_fuel(fuel).map_err(|e| error(e.to_string()))?;
+
+		let linker = wasi(module).map_err(error)?;
+		let trapped = |e: Error| match e.as_trap_code() {
+			Some(TrapCode::OutOfFuel) => error(format!(
+				"was stopped after {} seconds on {}",
+				timeout.as_secs(),
+				place
+			)),
+			_ => match e.i32_exit_status() {
+				Some(status) => error(format!("exited with status {} on {}", status, place)),
+				None => error(format!("{} on {}", e, place)),
+			},
+		};
+		let instance = linker
+			.instantiate(&mut store, module)
+			.and_then(|pre| pre.start(&mut store))
+			.map_err(trapped)?;
+		// modules built as libraries with wasi have to set themselves up first
+		if let Ok(initialize) = instance.get_typed_func::<(), ()>(&store, "_initialize") {
+			initialize.call(&mut store, ()).map_err(trapped)?;
+		}
+
+		let memory = instance
+			.get_memory(&store, "memory")
+			.ok_or_else(|| error(String::from("doesn't export its `memory`")))?;
+		let missing = |name: &str, e: Error| {
+			error(format!(
+				"doesn't export `{}` the way pillar expects: {}",
+				name, e
+			))
+		};
+		let alloc = instance
+			.get_typed_func::<i32, i32>(&store, "pillar_alloc")
+			.map_err(|e| missing("pillar_alloc", e))?;
+		let run = instance
+			.get_typed_func::<(i32, i32), i64>(&store, "pillar_run")
+			.map_err(|e| missing("pillar_run", e))?;
+
+		let length = input.len() as i32;
+		let address = alloc.call(&mut store, length).map_err(trapped)?;
+		memory
+			.write(&mut store, address as u32 as usize, input.as_bytes())
+			.map_err(|e| error(format!("gave an address its input doesn't fit at: {}", e)))?;
+		let reply = run.call(&mut store, (address, length)).map_err(trapped)?;
+		flush(store.data_mut());
+
+		let (address, length) = ((reply >> 32) as u32 as usize, reply as u32 as usize);
+		let reply = memory
+			.data(&store)
+			.get(address..address + length)
+			.ok_or_else(|| error(format!("gave a reply outside its memory on {}", place)))?;
+		String::from_utf8(reply.to_vec())
+			.map_err(|_| error(format!("gave a reply that isn't utf-8 on {}", place)))
+	}
+}
+
+/*
+the bits of wasi a module can use
+anything else it imports from wasi fails with "not available" when it's called,
+since most programs import more than they use, but importing from anywhere else is an error
+*/
+fn wasi(module: &Module) -> Result<Linker<Host>, String> {
+	const WASI: &str = "wasi_snapshot_preview1";
+	const PROVIDED: [&str; 9] = [
+		"fd_write",
+		"proc_exit",
+		"environ_sizes_get",
+		"args_sizes_get",
+		"environ_get",
+		"args_get",
+		"clock_time_get",
+		"random_get",
+		"fd_prestat_get",
+	];
+	let mut linker = Linker::<Host>::new(module.engine());
+	let defined = linker
+		.func_wrap(WASI, "fd_write", fd_write)
+		.and_then(|l| {
+			l.func_wrap(WASI, "proc_exit", |_: Caller<Host>, status: i32| {
+				Err::<(), _>(Error::i32_exit(status))
+			})
+		})
+		.and_then(|l| l.func_wrap(WASI, "environ_sizes_get", nothing))
+		.and_then(|l| l.func_wrap(WASI, "args_sizes_get", nothing))
+		.and_then(|l| {
+			l.func_wrap(WASI, "environ_get", |_: Caller<Host>, _: i32, _: i32| {
+				SUCCESS
+			})
+		})
+		.and_then(|l| l.func_wrap(WASI, "args_get", |_: Caller<Host>, _: i32, _: i32| SUCCESS))
+		.and_then(|l| l.func_wrap(WASI, "clock_time_get", clock_time_get))
+		.and_then(|l| l.func_wrap(WASI, "random_get", random_get))
+		// there are no files to open, so there's nothing already open
+		.and_then(|l| {
+			l.func_wrap(WASI, "fd_prestat_get", |_: Caller<Host>, _: i32, _: i32| {
+				BAD_FILE
+			})
+		});
+	if let Err(e) = defined {
+		return Err(e.to_string());
+	}
+
+	for import in module.imports() {
+		match import.ty() {
+			_ if import.module() == WASI && PROVIDED.contains(&import.name()) => (),
+			ExternType::Func(ty) if import.module() == WASI => {
+				let name = import.name().to_string();
+				let errno = ty.results() == [ValType::I32];
+				linker
+					.func_new(WASI, import.name(), ty.clone(), move |_, _, results| {
+						if errno {
+							results[0] = Val::I32(NOT_AVAILABLE);
+							Ok(())
+						} else {
+							Err(Error::new(format!("`{}` isn't available to plugins", name)))
+						}
+					})
+					.map_err(|e| e.to_string())?;
+			}
+			_ => {
+				return Err(format!(
+					"imports `{}` from `{}`, which pillar doesn't have",
+					import.name(),
+					import.module()
+				))
+			}
+		}
+	}
+	Ok(linker)
+}
+
+// only writes to stdout and stderr, which are printed with the plugin's name
+fn fd_write(
+	mut caller: Caller<Host>,
+	fd: i32,
+	iovs: i32,
+	count: i32,
+	written: i32,
+) -> Result<i32, Error> {
+	if fd != 1 && fd != 2 {
+		return Ok(BAD_FILE);
+	}
+	let memory = match caller.get_export("memory").and_then(Extern::into_memory) {
+		Some(memory) => memory,
+		None => return Ok(BAD_FILE),
+	};
+	let mut bytes = Vec::new();
+	for i in 0..count as u32 as usize {
+		let iov = iovs as u32 as usize + i * 8;
+		let address = read_u32(&memory, &caller, iov)? as usize;
+		let length = read_u32(&memory, &caller, iov + 4)? as usize;
+		let data = memory
+			.data(&caller)
+			.get(address..address + length)
+			.ok_or_else(|| Error::new("wrote from outside its memory"))?;
+		// the same memory written over and over could be much more than the module's allowed
+		if bytes.len() + data.len() > memory.data(&caller).len() {
+			return Err(Error::new("wrote more at once than it has memory"));
+		}
+		bytes.extend_from_slice(data);
+	}
+	memory
+		.write(
+			&mut caller,
+			written as u32 as usize,
+			&(bytes.len() as u32).to_le_bytes(),
+		)
+		.map_err(|e| Error::new(e.to_string()))?;
+
+	let host = caller.data_mut();
+	host.printed.extend(bytes);
+	while let Some(end) = host.printed.iter().position(|b| *b == b'\n') {
+		let line = host.printed.drain(..=end).collect::<Vec<_>>();
+		let line = String::from_utf8_lossy(&line[..end]);
+		println!("[{} on {}] {}", host.plugin, host.place, line);
+	}
+	Ok(SUCCESS)
+}
+
+// prints anything the module printed without a newline at the end
+fn flush(host: &mut Host) {
+	if !host.printed.is_empty() {
+		let line = std::mem::take(&mut host.printed);
+		let line = String::from_utf8_lossy(&line);
+		println!("[{} on {}] {}", host.plugin, host.place, line);
+	}
+}
+
+// there are no environment variables or arguments
+fn nothing(mut caller: Caller<Host>, count: i32, size: i32) -> Result<i32, Error> {
+	write(&mut caller, count, &0u32.to_le_bytes())?;
+	write(&mut caller, size, &0u32.to_le_bytes())?;
+	Ok(SUCCESS)
+}
+
+fn clock_time_get(mut caller: Caller<Host>, _: i32, _: i64, time: i32) -> Result<i32, Error> {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_nanos() as u64)
+		.unwrap_or_default();
+	write(&mut caller, time, &now.to_le_bytes())?;
+	Ok(SUCCESS)
+}
+
+// std's hash maps ask for random numbers when they're made, so these don't need to be any better than theirs
+// they're written straight into the module's memory, so asking for more than it has can't make pillar allocate it
+fn random_get(mut caller: Caller<Host>, buffer: i32, length: i32) -> Result<i32, Error> {
+	let memory = caller
+		.get_export("memory")
+		.and_then(Extern::into_memory)
+		.ok_or_else(|| Error::new("doesn't export its `memory`"))?;
+	let start = buffer as u32 as usize;
+	let data = match memory
+		.data_mut(&mut caller)
+		.get_mut(start..start + length as u32 as usize)
+	{
+		Some(data) => data,
+		None => return Ok(BAD_ADDRESS),
+	};
+	for chunk in data.chunks_mut(8) {
+		let random = RandomState::new().build_hasher().finish().to_le_bytes();
+		chunk.copy_from_slice(&random[..chunk.len()]);
+	}
+	Ok(SUCCESS)
+}
+
+fn write(caller: &mut Caller<Host>, address: i32, bytes: &[u8]) -> Result<(), Error> {
+	let memory = caller
+		.get_export("memory")
+		.and_then(Extern::into_memory)
+		.ok_or_else(|| Error::new("doesn't export its `memory`"))?;
+	memory
+		.write(caller, address as u32 as usize, bytes)
+		.map_err(|e| Error::new(e.to_string()))
+}
+
+fn read_u32(memory: &Memory, caller: &Caller<Host>, address: usize) -> Result<u32, Error> {
+	let mut bytes = [0; 4];
+	memory
+		.read(caller, address, &mut bytes)
+		.map_err(|e| Error::new(e.to_string()))?;
+	Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::pipeline::Stage;
+	use std::path::Path;
+
+	// a module that replies with `{"content": "..."}` around its input, after printing and asking for the time
+	const ECHO: &str = r#"
+		(module
+			(import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+			(import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+			(import "wasi_snapshot_preview1" "path_open"
+				(func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+			(memory (export "memory") 1)
+			(data (i32.const 0) "{\"content\": \"")
+			(data (i32.const 16) "hello\n")
+			(data (i32.const 32) "\10\00\00\00\06\00\00\00")
+			(func (export "pillar_alloc") (param i32) (result i32) (i32.const 1024))
+			(func (export "pillar_run") (param $input i32) (param $length i32) (result i64)
+				(drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))
+				(drop (call $clock (i32.const 0) (i64.const 0) (i32.const 48)))
+				(if (i32.ne (call $open (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+						(i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 0)) (i32.const 52))
+					(then unreachable))
+				;; the input goes right after `{"content": "`, and `"}` after it
+				(memory.copy (i32.const 13) (local.get $input) (local.get $length))
+				(i32.store16 (i32.add (i32.const 13) (local.get $length)) (i32.const 0x7d22))
+				(i64.add (i64.const 0) (i64.extend_i32_u (i32.add (local.get $length) (i32.const 15))))))
+	"#;
+
+	fn module(dir: &Path, name: &str, wat: &str) -> Plugin {
+		let run = dir.join(format!("{}.wasm", name));
+		fs::write(&run, wat::parse_str(wat).unwrap()).unwrap();
+		let mut plugin = Plugin::single(name.to_string(), run);
+		plugin.stage = Stage::PostTemplate;
+		plugin
+	}
+
+	#[test]
+	fn test_wasm() {
+		let dir = std::env::temp_dir().join(format!("pillar-wasm-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let modules = Modules::new(1);
+		let timeout = Duration::from_secs(1);
+
+		let echo = module(&dir, "echo", ECHO);
+		let reply = modules.run(&echo, "hi", "test.gn", timeout).unwrap();
+		assert_eq!("{\"content\": \"hi\"}", reply);
+
+		let spin = module(
+			&dir,
+			"spin",
+			r#"(module (memory (export "memory") 1)
+				(func (export "pillar_alloc") (param i32) (result i32) (i32.const 0))
+				(func (export "pillar_run") (param i32 i32) (result i64) (loop (br 0)) (i64.const 0)))"#,
+		);
+		let stopped = modules.run(&spin, "hi", "test.gn", timeout);
+		assert!(stopped.unwrap_err().message.starts_with("was stopped"));
+
+		// more memory than the megabyte it's allowed
+		let greedy = module(
+			&dir,
+			"greedy",
+			r#"(module (memory (export "memory") 1)
+				(func (export "pillar_alloc") (param i32) (result i32) (i32.const 0))
+				(func (export "pillar_run") (param i32 i32) (result i64)
+					(if (i32.eq (memory.grow (i32.const 32)) (i32.const -1)) (then unreachable))
+					(i64.const 0)))"#,
+		);
+		assert!(modules.run(&greedy, "hi", "test.gn", timeout).is_err());
+
+		// random bytes only go inside the module's memory
+		let random = module(
+			&dir,
+			"random",
+			r#"(module
+				(import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+				(memory (export "memory") 1)
+				(func (export "pillar_alloc") (param i32) (result i32) (i32.const 1024))
+				(func (export "pillar_run") (param i32 i32) (result i64)
+					(if (i32.ne (call $random (i32.const 0) (i32.const 13)) (i32.const 0)) (then unreachable))
+					(if (i32.ne (call $random (i32.const 16) (i32.const -1)) (i32.const 21)) (then unreachable))
+					(if (i32.ne (call $random (i32.const 65530) (i32.const 7)) (i32.const 21)) (then unreachable))
+					(i64.const 0)))"#,
+		);
+		assert_eq!("", modules.run(&random, "hi", "test.gn", timeout).unwrap());
+
+		let outside = module(
+			&dir,
+			"outside",
+			r#"(module (import "env" "system" (func (param i32))) (memory (export "memory") 1))"#,
+		);
+		let refused = modules.run(&outside, "hi", "test.gn", timeout);
+		assert!(refused.unwrap_err().message.contains("pillar doesn't have"));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,296 @@
+use super::{Plugin, PluginError};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+a plugin that's started once and then sent every request for it over stdin, instead of being started for each one
+each request and reply is framed as its length in bytes on a line, then that many bytes:
+```
+29
+{"protocol": 2, "...": "..."}
+```
+the worker is stopped by closing its stdin once the build is done, so it should exit when it reads the end of it
+*/
+pub struct Worker {
+	name: String,
+	process: Child,
+	// frames to write to the worker's stdin, which is closed when this is dropped
+	requests: Option<Sender<Vec<u8>>>,
+	// frames read from its stdout, or `None` when it's closed
+	replies: Receiver<io::Result<Option<Vec<u8>>>>,
+	// the page the worker is working on, for printing its stderr
+	place: Arc<Mutex<String>>,
+	stderr: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+	// `command` has everything but the page, since a worker isn't started for any one page
+	pub fn start(plugin: &Plugin, mut command: Command) -> Result<Worker, PluginError> {
+		let mut process = command
+			.env("PILLAR_WORKER", "1")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| PluginError {
+				plugin: plugin.name.clone(),
+				message: format!("couldn't start {}: {}", plugin.run.display(), e),
+			})?;
+
+		// each pipe has its own thread, so a worker that writes while it's still reading can't get stuck
+		let mut stdin = process.stdin.take().unwrap();
+		let (requests, to_write) = mpsc::channel::<Vec<u8>>();
+		thread::spawn(move || {
+			for request in to_write {
+				if stdin
+					.write_all(&request)
+					.and_then(|_| stdin.flush())
+					.is_err()
+				{
+					break;
+				}
+			}
+		});
+
+		let mut stdout = BufReader::new(process.stdout.take().unwrap());
+		let (send, replies) = mpsc::channel();
+		thread::spawn(move || loop {
+			let reply = read_frame(&mut stdout);
+			let done = !matches!(reply, Ok(Some(_)));
+			if send.send(reply).is_err() || done {
+				break;
+			}
+		});
+
+		let stderr = BufReader::new(process.stderr.take().unwrap());
+		let place = Arc::new(Mutex::new(String::from("the site")));
+		let (name, printing) = (plugin.name.clone(), place.clone());
+		let stderr = thread::spawn(move || {
+			for line in stderr.lines().map_while(Result::ok) {
+				let place = printing.lock().map(|p| p.clone()).unwrap_or_default();
+				println!("[{} on {}] {}", name, place, line);
+			}
+		});
+
+		Ok(Worker {
+			name: plugin.name.clone(),
+			process,
+			requests: Some(requests),
+			replies,
+			place,
+			stderr: Some(stderr),
+		})
+	}
+
+	/*
+	sends the worker one request and waits for its reply
+	a worker that doesn't reply in time, or exits before it does, is an error,
+	and shouldn't be sent anything else
+	*/
+	pub fn request(
+		&mut self,
+		input: &str,
+		place: &str,
+		timeout: Duration,
+	) -> Result<String, PluginError> {
+		if let Ok(mut p) = self.place.lock() {
+			*p = place.to_string();
+		}
+		let sent = match &self.requests {
+			Some(requests) => requests.send(frame(input.as_bytes())).is_ok(),
+			None => false,
+		};
+		if !sent {
+			return Err(self.error(format!("isn't running any more on {}", place)));
+		}
+		let reply = match self.replies.recv_timeout(timeout) {
+			Ok(Ok(Some(reply))) => reply,
+			Ok(Err(e)) => {
+				return Err(self.error(format!(
+					"sent a reply that couldn't be read on {}: {}",
+					place, e
+				)))
+			}
+			Err(RecvTimeoutError::Timeout) => {
+				return Err(self.error(format!(
+					"was stopped after {} seconds on {}",
+					timeout.as_secs(),
+					place
+				)))
+			}
+			// its stdout was closed, which usually means it's exited
+			Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => {
+				let start = Instant::now();
+				let status = loop {
+					match self.process.try_wait() {
+						Ok(None) if start.elapsed() < timeout => {
+							thread::sleep(Duration::from_millis(5))
+						}
+						Ok(Some(status)) => break status.code(),
+						_ => break None,
+					}
+				};
+				let status = match status {
+					Some(code) => format!("exited with status {}", code),
+					None => String::from("stopped"),
+				};
+				return Err(self.error(format!("{} before replying on {}", status, place)));
+			}
+		};
+		String::from_utf8(reply)
+			.map_err(|_| self.error(format!("wrote something that isn't utf-8 on {}", place)))
+	}
+
+	/*
+	closes the worker's stdin and waits for it to exit,
+	stopping it if it's still running after `timeout`
+	*/
+	pub fn stop(mut self, timeout: Duration) -> Result<(), PluginError> {
+		self.requests = None;
+		let start = Instant::now();
+		let result = loop {
+			match self.process.try_wait() {
+				Ok(Some(status)) if status.success() => break Ok(()),
+				Ok(Some(status)) => {
+					break Err(self.error(match status.code() {
+						Some(code) => {
+							format!("exited with status {} at the end of the build", code)
+						}
+						None => String::from("was killed at the end of the build"),
+					}))
+				}
+				Ok(None) if start.elapsed() > timeout => {
+					break Err(self.error(format!(
+						"was stopped {} seconds after the build, since it didn't exit when its stdin was closed",
+						timeout.as_secs()
+					)))
+				}
+				Ok(None) => thread::sleep(Duration::from_millis(5)),
+				Err(e) => break Err(self.error(format!("couldn't wait for it to finish: {}", e))),
+			}
+		};
+		// anything left on stderr is printed before moving on
+		let _ = self.process.kill();
+		let _ = self.process.wait();
+		if let Some(stderr) = self.stderr.take() {
+			let _ = stderr.join();
+		}
+		result
+	}
+
+	fn error(&self, message: String) -> PluginError {
+		PluginError {
+			plugin: self.name.clone(),
+			message,
+		}
+	}
+}
+
+// a worker that's dropped without being stopped, like after an error, isn't left running
+impl Drop for Worker {
+	fn drop(&mut self) {
+		self.requests = None;
+		let _ = self.process.kill();
+		let _ = self.process.wait();
+	}
+}
+
+fn frame(bytes: &[u8]) -> Vec<u8> {
+	let mut framed = format!("{}\n", bytes.len()).into_bytes();
+	framed.extend_from_slice(bytes);
+	framed
+}
+
+// reads one frame, or `None` if the other end has closed it first
+fn read_frame<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+	let mut length = String::new();
+	if reader.read_line(&mut length)? == 0 {
+		return Ok(None);
+	}
+	let length = length.trim().parse::<usize>().map_err(|_| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("expected the length of the reply, not `{}`", length.trim()),
+		)
+	})?;
+	let mut bytes = vec![0; length];
+	reader.read_exact(&mut bytes)?;
+	Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::plugins::Plugins;
+
+	#[test]
+	fn test_frame() {
+		let framed = [frame("{\"content\": \"é\"}".as_bytes()), frame(b"")].concat();
+		assert_eq!(b"17\n{\"content\": \"\xc3\xa9\"}0\n", framed.as_slice());
+
+		let mut reader = framed.as_slice();
+		let first = read_frame(&mut reader).unwrap().unwrap();
+		assert_eq!("{\"content\": \"é\"}", String::from_utf8(first).unwrap());
+		assert_eq!(Some(Vec::new()), read_frame(&mut reader).unwrap());
+		assert_eq!(None, read_frame(&mut reader).unwrap());
+		assert!(read_frame(&mut "five\nbytes".as_bytes()).is_err());
+	}
+
+	#[test]
+	fn test_worker() {
+		// replies with its process id, so it's clear which process each request went to
+		let worker = r#"#!/bin/sh
+while read -r length; do
+	body=$(head -c "$length")
+	case "$body" in *crash*) exit 4;; esac
+	reply="$$"
+	printf '%s\n%s' "${#reply}" "$reply"
+done
+"#;
+		let (dir, config) = crate::plugins::test::project(
+			"worker",
+			&[
+				(
+					"counter/plugin.toml",
+					"run = \"counter.sh\"\nprotocol = \"json\"\nworker = true\n",
+				),
+				("counter/counter.sh", worker),
+			],
+		);
+		let plugins = Plugins::new(&config, &[], crate::plugins::test::options(&dir)).unwrap();
+		let plugin = plugins
+			.plugins
+			.iter()
+			.find(|p| p.name == "counter")
+			.unwrap();
+		let timeout = Duration::from_secs(5);
+		let request = |input: &str| plugins.request(plugin, "the site", input, timeout);
+
+		let first = request("one").unwrap();
+		assert_eq!(first, request("two").unwrap());
+		let error = request("crash").unwrap_err();
+		assert_eq!(
+			"exited with status 4 before replying on the site",
+			error.message
+		);
+		assert!(plugins.workers.borrow().is_empty());
+
+		// it's started again for the next request
+		let restarted = request("three").unwrap();
+		assert_ne!(first, restarted);
+		plugins.finish().unwrap();
+		assert!(plugins.workers.borrow().is_empty());
+		let running = Command::new("kill")
+			.args(["-0", &restarted])
+			.stderr(Stdio::null())
+			.status()
+			.unwrap();
+		assert!(!running.success());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
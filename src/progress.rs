@@ -50,7 +50,7 @@ pub fn terminal_size() -> Option<(u16, u16)> {
 
 	// stdout is "rows cols"
 	let mut data = stdout.split_whitespace();
-	let rows = u16::from_str_radix(data.next().unwrap(), 10).unwrap();
-	let cols = u16::from_str_radix(data.next().unwrap(), 10).unwrap();
+	let rows = data.next().unwrap().parse::<u16>().unwrap();
+	let cols = data.next().unwrap().parse::<u16>().unwrap();
 	Some((rows, cols))
 }
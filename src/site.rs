@@ -0,0 +1,393 @@
+use crate::config::Config;
+use crate::granite::*;
+use crate::utils::text::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::SystemTime;
+use toml::value::{Table, Value};
+use walkdir::{DirEntry, WalkDir};
+
+/*
+a granite file and the html file it's built into, with its meta header already read
+*/
+pub struct Source {
+	// the granite file, like `pages/notes/uxn.gn`
+	pub path: String,
+	// the html file, like `docs/notes/uxn.html`
+	pub target: String,
+	// the html file relative to the html directory, like `notes/uxn.html`
+	pub url: String,
+	pub meta: Vec<Metadata>,
+	pub modified: u64,
+	pub created: u64,
+	// the full url of the page, if `base_url` is set, or just `url` if it isn't
+	pub permalink: String,
+}
+
+impl Source {
+	pub fn meta(&self, name: &str) -> Option<&str> {
+		self.meta
+			.iter()
+			.rev()
+			.find(|m| m.name == name)
+			.map(|m| m.value.as_str())
+	}
+
+	/*
+	the variables templates see for this page, as `page.*` or as an item of `pages`
+	every meta variable is there as text, except `tags`, which is split on commas into a list
+	*/
+	pub fn vars(&self) -> Table {
+		let mut vars = Table::new();
+		for meta in &self.meta {
+			vars.insert(meta.name.clone(), Value::String(meta.value.clone()));
+		}
+		vars.insert(
+			String::from("tags"),
+			Value::Array(self.tags().into_iter().map(Value::String).collect()),
+		);
+		vars.insert(String::from("url"), Value::String(self.url.clone()));
+		vars.insert(
+			String::from("permalink"),
+			Value::String(self.permalink.clone()),
+		);
+		vars.insert(String::from("source"), Value::String(self.path.clone()));
+		vars.insert(String::from("output"), Value::String(self.target.clone()));
+		vars.insert(
+			String::from("modified"),
+			Value::Integer(self.modified as i64),
+		);
+		vars.insert(String::from("created"), Value::Integer(self.created as i64));
+		vars
+	}
+
+	/*
+	the way back up to the html directory from this page, like `../../` for `notes/uxn/intro.html`,
+	so links written relative to the site's root work from any page
+	*/
+	pub fn root(&self) -> String {
+		"../".repeat(self.url.matches('/').count())
+	}
+
+	pub fn tags(&self) -> Vec<String> {
+		match self.meta("tags") {
+			Some(tags) => tags
+				.split(',')
+				.map(|t| t.trim().to_string())
+				.filter(|t| !t.is_empty())
+				.collect(),
+			None => Vec::new(),
+		}
+	}
+}
+
+fn is_not_hidden(entry: &DirEntry) -> bool {
+	entry
+		.file_name()
+		.to_str()
+		.map(|s| entry.depth() == 0 || !s.starts_with('.'))
+		.unwrap_or(false)
+}
+
+/*
+goes through every file in the granite directory and reads its meta header,
+so every page can know about every other page before any of them are built
+*/
+pub fn index(config: &Config) -> std::io::Result<Vec<Source>> {
+	let walk = WalkDir::new(&config.granite_path)
+		.sort_by(|a, b| a.file_name().cmp(b.file_name()))
+		.into_iter()
+		.filter_entry(is_not_hidden)
+		.filter_map(|v| v.ok())
+		.filter(|e| !e.file_type().is_dir());
+
+	let mut sources = Vec::new();
+	for entry in walk {
+		let path = entry.path().display().to_string();
+
+		// gets and formats file metadata, modified and creation time
+		let meta = fs::metadata(&path)?;
+		let modified = meta.mtime() as u64;
+		let created = match meta.created() {
+			Ok(c) => c.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
+			Err(_) => modified,
+		};
+
+		let contents = fs::read_to_string(&path)?;
+		// -> granite.rs:[parse_header(l: &str)]
+		let page_vars = parse_header(&contents).meta;
+
+		// formats target string to look like html_path/file.html
+		let url = [
+			slice(&path, len(&config.granite_path)..len(&path) - 2),
+			String::from("html"),
+		]
+		.concat();
+		let target = [config.html_path.clone(), url.clone()].concat();
+		let permalink = absolute(config.base_url.as_deref(), &url);
+
+		sources.push(Source {
+			path,
+			target,
+			url,
+			meta: page_vars,
+			modified,
+			created,
+			permalink,
+		});
+	}
+	Ok(sources)
+}
+
+// links that already go somewhere on their own, and shouldn't be made relative to the site
+fn is_external(url: &str) -> bool {
+	url.contains("://")
+		|| url.starts_with("//")
+		|| url.starts_with('#')
+		|| url.starts_with("mailto:")
+		|| url.starts_with("data:")
+}
+
+/*
+a link to something in the html directory, from the page `root` leads back up from
+granite files are linked to as the html they're built into, so `notes/uxn.gn` is `notes/uxn.html`
+*/
+pub fn url_for(root: &str, path: &str) -> String {
+	if is_external(path) {
+		return path.to_string();
+	}
+	let path = path.trim_start_matches('/');
+	let path = match path.strip_suffix(".gn") {
+		Some(page) => format!("{}.html", page),
+		None => path.to_string(),
+	};
+	[root, &path].concat()
+}
+
+/*
+the full url of something in the html directory, using the `base_url` from [values]
+without a `base_url` the path is given back as it is
+*/
+pub fn absolute(base_url: Option<&str>, path: &str) -> String {
+	if is_external(path) {
+		return path.to_string();
+	}
+	match base_url {
+		Some(base) => format!("{}/{}", base.trim_end_matches('/'), url_for("", path)),
+		None => url_for("", path),
+	}
+}
+
+/*
+fills in `{{root}}` and `{{url_for "notes/uxn.gn"}}` in a page's parsed content,
+which templates do themselves but pages don't go through
+*/
+pub fn links(content: &str, root: &str) -> String {
+	lazy_static! {
+		static ref LINK: Regex =
+			Regex::new(r#"\{\{\s*(?:root|url_for\s+(?:"([^"]*)"|'([^']*)'|([^\s"'}]+)))\s*\}\}"#)
+				.unwrap();
+	}
+	LINK.replace_all(content, |caps: &Captures| {
+		match caps.get(1).or_else(|| caps.get(2)).or_else(|| caps.get(3)) {
+			Some(path) => url_for(root, path.as_str()),
+			None => root.to_string(),
+		}
+	})
+	.to_string()
+}
+
+lazy_static! {
+	static ref HREF: Regex = Regex::new(r#"(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/*
+the things a built page links to in the html directory, from its `href`s and `src`s,
+as urls relative to the html directory like `notes/uxn.html`, so `../index.html` from `notes/uxn.html` is `index.html`
+*/
+pub fn linked(html: &str, url: &str) -> Vec<String> {
+	let mut linked = Vec::<String>::new();
+	for caps in HREF.captures_iter(html) {
+		let link = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+		if link.is_empty() || is_external(link) {
+			continue;
+		}
+		let link = resolve(link.split(['#', '?']).next().unwrap_or_default(), url);
+		if !link.is_empty() && !linked.contains(&link) {
+			linked.push(link);
+		}
+	}
+	linked
+}
+
+/*
+makes every link in some html from the page at `url` a full url, with the `base_url` from [values],
+so it still works away from the site, like in a feed reader
+*/
+pub fn absolute_links(html: &str, url: &str, base_url: &str) -> String {
+	HREF.replace_all(html, |caps: &Captures| {
+		let whole = caps.get(0).unwrap();
+		let value = caps.get(1).or_else(|| caps.get(2)).unwrap();
+		let link = value.as_str();
+		if is_external(link) {
+			return whole.as_str().to_string();
+		}
+		// a link like `?page=2` is to the page itself
+		let at = link.find(['#', '?']).unwrap_or(link.len());
+		let path = match &link[..at] {
+			"" => url.to_string(),
+			path => resolve(path, url),
+		};
+		let full = [absolute(Some(base_url), &path).as_str(), &link[at..]].concat();
+		// only the value changes, wherever else its text shows up in the attribute
+		let start = value.start() - whole.start();
+		let end = value.end() - whole.start();
+		[&whole.as_str()[..start], &full, &whole.as_str()[end..]].concat()
+	})
+	.to_string()
+}
+
+/*
+a link's path from the page at `url` as a path relative to the html directory,
+so `../index.html` from `notes/uxn.html` is `index.html`
+*/
+fn resolve(link: &str, url: &str) -> String {
+	// starts from the page's directory, unless the link starts from the top of the site
+	let mut parts = match link.strip_prefix('/') {
+		Some(_) => Vec::new(),
+		None => url.split('/').collect::<Vec<_>>(),
+	};
+	parts.pop();
+	for part in link.split('/') {
+		match part {
+			"" | "." => (),
+			".." => {
+				parts.pop();
+			}
+			_ => parts.push(part),
+		}
+	}
+	parts.join("/")
+}
+
+/*
+the variables every page's template can use, no matter which page it is:
+`site.*` from the [values] config table, `pages` for every page, `tags` for every tag and the pages with it,
+and `data.*` for each toml file in the data directory
+*/
+pub fn globals(config: &Config, sources: &[Source]) -> std::io::Result<Table> {
+	let pages = sources
+		.iter()
+		.map(|s| Value::Table(s.vars()))
+		.collect::<Vec<_>>();
+
+	let mut names = sources.iter().flat_map(|s| s.tags()).collect::<Vec<_>>();
+	names.sort();
+	names.dedup();
+	let tags = names
+		.into_iter()
+		.map(|name| {
+			let tagged = sources
+				.iter()
+				.filter(|s| s.tags().contains(&name))
+				.map(|s| Value::Table(s.vars()))
+				.collect();
+			let mut tag = Table::new();
+			tag.insert(String::from("name"), Value::String(name));
+			tag.insert(String::from("pages"), Value::Array(tagged));
+			Value::Table(tag)
+		})
+		.collect();
+
+	let mut vars = Table::new();
+	vars.insert(String::from("site"), Value::Table(config.values.clone()));
+	vars.insert(String::from("pages"), Value::Array(pages));
+	vars.insert(String::from("tags"), Value::Array(tags));
+	vars.insert(String::from("data"), Value::Table(data(&config.data_path)?));
+	Ok(vars)
+}
+
+/*
+reads every toml file in the data directory into a table, named after the file
+*/
+fn data(dir: &str) -> std::io::Result<Table> {
+	let mut data = Table::new();
+	if !Path::new(dir).is_dir() {
+		return Ok(data);
+	}
+	let mut files = fs::read_dir(dir)?
+		.filter_map(|e| e.ok())
+		.map(|e| e.path())
+		.collect::<Vec<_>>();
+	files.sort();
+	for file in files {
+		if file.extension().and_then(|e| e.to_str()) != Some("toml") {
+			continue;
+		}
+		let name = file.file_stem().unwrap().to_string_lossy().to_string();
+		let value = fs::read_to_string(&file)?.parse::<Value>().map_err(|e| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("couldn't parse {}: {}", file.display(), e),
+			)
+		})?;
+		data.insert(name, value);
+	}
+	Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_url_for() {
+		assert_eq!("../../style.css", url_for("../../", "style.css"));
+		assert_eq!("../notes/uxn.html", url_for("../", "/notes/uxn.gn"));
+		assert_eq!(
+			"https://wiki.xxiivv.com",
+			url_for("../", "https://wiki.xxiivv.com")
+		);
+		assert_eq!(
+			"https://example.com/notes/uxn.html",
+			absolute(Some("https://example.com/"), "notes/uxn.gn")
+		);
+		assert_eq!("notes/uxn.html", absolute(None, "notes/uxn.html"));
+	}
+
+	#[test]
+	fn test_links() {
+		let content = "<a href=\"{{root}}index.html\">home</a> <a href=\"{{ url_for 'notes/uxn.gn' }}\">uxn</a> {{date}}";
+		let expect =
+			"<a href=\"../index.html\">home</a> <a href=\"../notes/uxn.html\">uxn</a> {{date}}";
+		assert_eq!(expect, links(content, "../"));
+	}
+
+	#[test]
+	fn test_linked() {
+		let html = "<a href=\"../index.html#top\">home</a> <img src='uxn.png'> <a href=\"/about.html\"></a> <a href=\"https://wiki.xxiivv.com\"></a> <a href=\"#notes\"></a>";
+		assert_eq!(
+			vec!["index.html", "notes/uxn.png", "about.html"],
+			linked(html, "notes/uxn.html")
+		);
+	}
+
+	#[test]
+	fn test_absolute_links() {
+		let html = "<a href=\"../index.html#top\">home</a> <img src='uxn.png'> <a href=\"#notes\"></a> <a href=\"https://wiki.xxiivv.com\"></a>";
+		assert_eq!(
+			"<a href=\"https://example.com/index.html#top\">home</a> <img src='https://example.com/notes/uxn.png'> <a href=\"#notes\"></a> <a href=\"https://wiki.xxiivv.com\"></a>",
+			absolute_links(html, "notes/uxn.html", "https://example.com")
+		);
+		// links that are empty, short, or part of the attribute's name
+		let html =
+			"<a href=\"\">here</a> <a href=\"e\">e</a> <a href=\"href\">href</a> <img src='s'>";
+		assert_eq!(
+			"<a href=\"https://example.com/notes/uxn.html\">here</a> <a href=\"https://example.com/notes/e\">e</a> <a href=\"https://example.com/notes/href\">href</a> <img src='https://example.com/notes/s'>",
+			absolute_links(html, "notes/uxn.html", "https://example.com")
+		);
+	}
+}
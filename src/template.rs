@@ -0,0 +1,1586 @@
+use crate::config::Config;
+use crate::filters::*;
+use crate::granite::*;
+use crate::site;
+use crate::site::Source;
+use crate::utils::glob;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::rc::Rc;
+use toml::value::{Table, Value};
+
+#[derive(Debug)]
+pub struct TemplateError {
+	pub template: String,
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		write!(fmt, "{}:{}: {}", self.template, self.line, self.message)
+	}
+}
+
+enum Node {
+	// plain text, copied to the output as is
+	Text(String),
+	// a `{{ ... }}` tag, `raw` is kept so a bare name we can't resolve, like `{{latest}}`, is left for the plugins
+	Var {
+		expr: Option<Expr>,
+		raw: String,
+		line: usize,
+	},
+	// a `{% block name %}`, which templates extending this one can replace
+	Block {
+		name: String,
+		line: usize,
+		body: Vec<Node>,
+	},
+	// an `{% include "name" key: value %}` of a template from the partials directory
+	Include {
+		name: String,
+		line: usize,
+		params: Vec<(String, Arg)>,
+	},
+	// `{% if %}`, any `{% elif %}`s (with the line each is on), and what's between `{% else %}` and `{% endif %}`
+	If {
+		branches: Vec<(Cond, usize, Vec<Node>)>,
+		otherwise: Vec<Node>,
+	},
+	// `{% for name in list %}`, with what's between `{% else %}` and `{% endfor %}` for empty lists
+	For {
+		name: String,
+		list: Expr,
+		line: usize,
+		body: Vec<Node>,
+		otherwise: Vec<Node>,
+	},
+}
+
+// a value given in a tag, either written out or the name of a variable
+enum Arg {
+	Value(Value),
+	Path(String),
+	// `url_for "notes/uxn.gn"`, a link made relative to the page being built
+	Url(Box<Arg>),
+}
+
+impl Arg {
+	fn resolve(&self, scope: &Scope) -> Option<Value> {
+		match self {
+			Arg::Value(v) => Some(v.clone()),
+			Arg::Path(path) => scope.lookup(path).cloned(),
+			Arg::Url(path) => {
+				let path = display(&path.resolve(scope)?)?;
+				let root = scope.lookup("root").and_then(|r| r.as_str()).unwrap_or("");
+				Some(Value::String(site::url_for(root, &path)))
+			}
+		}
+	}
+}
+
+// a value and the filters it's passed through, like `page.date | date "%d %B %Y"`
+struct Expr {
+	value: Arg,
+	filters: Vec<(String, Vec<Arg>)>,
+}
+
+impl Expr {
+	/*
+	finds the value and runs it through each filter, returning `None` if it names a variable that doesn't exist
+	a missing variable which has filters is treated as empty text instead, so `default` can fill it in
+	*/
+	fn eval(&self, scope: &Scope, filters: &Filters) -> Result<Option<Value>, String> {
+		let value = self.value.resolve(scope);
+		if self.filters.is_empty() {
+			return Ok(value);
+		}
+
+		let mut value = value.unwrap_or_else(|| Value::String(String::new()));
+		for (name, args) in &self.filters {
+			let args = args
+				.iter()
+				.map(|a| {
+					a.resolve(scope)
+						.unwrap_or_else(|| Value::String(String::new()))
+				})
+				.collect::<Vec<_>>();
+			value = filters
+				.apply(name, &value, &args)
+				.map_err(|e| format!("`{}` filter: {}", name, e))?;
+		}
+		Ok(Some(value))
+	}
+}
+
+// whether a tag is only a name, like `{{latest}}` or `{{ date }}`, so it could be for a plugin
+fn bare(expr: &Option<Expr>) -> bool {
+	match expr {
+		Some(e) => matches!(&e.value, Arg::Path(path) if !path.contains('.')),
+		None => true,
+	}
+}
+
+// a `page.*` or `site.*` variable, which is worth a warning when it's missing since it's often a typo
+fn expected(expr: &Option<Expr>) -> Option<&str> {
+	match expr.as_ref().map(|e| &e.value) {
+		Some(Arg::Path(path)) if path.starts_with("page.") || path.starts_with("site.") => {
+			Some(path)
+		}
+		_ => None,
+	}
+}
+
+// the condition of an `{% if %}`
+enum Cond {
+	Test(Expr),
+	Compare(Expr, &'static str, Expr),
+	Not(Box<Cond>),
+	And(Box<Cond>, Box<Cond>),
+	Or(Box<Cond>, Box<Cond>),
+}
+
+impl Cond {
+	fn eval(&self, scope: &Scope, filters: &Filters) -> Result<bool, String> {
+		Ok(match self {
+			Cond::Test(e) => truthy(e.eval(scope, filters)?.as_ref()),
+			Cond::Compare(a, op, b) => {
+				let empty = || Value::String(String::new());
+				let a = a.eval(scope, filters)?.unwrap_or_else(empty);
+				let b = b.eval(scope, filters)?.unwrap_or_else(empty);
+				let order = || match (&a, &b) {
+					(Value::Array(_), _) | (Value::Table(_), _) if a == b => Ordering::Equal,
+					(Value::Array(_), _) | (Value::Table(_), _) => Ordering::Less,
+					_ => compare(&a, &b),
+				};
+				match *op {
+					"==" => order() == Ordering::Equal,
+					"!=" => order() != Ordering::Equal,
+					"<" => order() == Ordering::Less,
+					">" => order() == Ordering::Greater,
+					"<=" => order() != Ordering::Greater,
+					">=" => order() != Ordering::Less,
+					// "in"
+					_ => contains(&b, &a),
+				}
+			}
+			Cond::Not(c) => !c.eval(scope, filters)?,
+			Cond::And(a, b) => a.eval(scope, filters)? && b.eval(scope, filters)?,
+			Cond::Or(a, b) => a.eval(scope, filters)? || b.eval(scope, filters)?,
+		})
+	}
+}
+
+// missing variables, `false`, zero, and empty text, lists and tables are false, everything else is true
+fn truthy(value: Option<&Value>) -> bool {
+	match value {
+		None => false,
+		Some(Value::Boolean(b)) => *b,
+		Some(Value::Integer(i)) => *i != 0,
+		Some(Value::Float(f)) => *f != 0.0,
+		Some(Value::String(s)) => !s.is_empty(),
+		Some(Value::Array(a)) => !a.is_empty(),
+		Some(Value::Table(t)) => !t.is_empty(),
+		Some(Value::Datetime(_)) => true,
+	}
+}
+
+// whether a list has an item, a table has a key, or text has some smaller text in it
+fn contains(haystack: &Value, needle: &Value) -> bool {
+	match haystack {
+		Value::Array(a) => a.iter().any(|v| compare(v, needle) == Ordering::Equal),
+		Value::Table(t) => display(needle).map(|k| t.contains_key(&k)).unwrap_or(false),
+		_ => match (display(haystack), display(needle)) {
+			(Some(h), Some(n)) => h.contains(&n),
+			_ => false,
+		},
+	}
+}
+
+/*
+the variables set inside a template (by a `for` or an `include`),
+looked through before the ones outside of it
+*/
+struct Scope<'a> {
+	vars: &'a Table,
+	parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+	fn lookup(&self, path: &str) -> Option<&Value> {
+		let first = path.split('.').next()?;
+		if self.vars.contains_key(first) {
+			return lookup(self.vars, path);
+		}
+		self.parent?.lookup(path)
+	}
+}
+
+// the pieces a tag is split into before it's parsed
+enum Token {
+	// a quoted string, without its quotes
+	Str(String),
+	// a name, variable path, number or keyword
+	Word(String),
+	// punctuation, like the `:` between a parameter's name and value or the `|` before a filter
+	Sym(&'static str),
+}
+
+// the symbols a tag can have, longest first so `<=` isn't read as `<` and then `=`
+const SYMBOLS: [&str; 11] = ["==", "!=", "<=", ">=", "<", ">", ":", ",", "|", "(", ")"];
+
+impl fmt::Display for Token {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Token::Str(s) => write!(fmt, "\"{}\"", s),
+			Token::Word(w) => fmt.write_str(w),
+			Token::Sym(s) => fmt.write_str(s),
+		}
+	}
+}
+
+// a `{% ... %}` tag that has been opened but not closed yet while parsing
+enum Open {
+	Block(String),
+	// the finished branches, and the condition of the current one (or `None` once it's reached `else`)
+	If(Vec<(Cond, usize, Vec<Node>)>, Option<(Cond, usize)>),
+	// the loop variable and list, and the loop body once it's reached `else`
+	For(String, Expr, Option<Vec<Node>>),
+}
+
+impl Open {
+	fn describe(&self) -> String {
+		match self {
+			Open::Block(name) => format!("block {}", name),
+			Open::If(..) => String::from("if"),
+			Open::For(name, ..) => format!("for {}", name),
+		}
+	}
+}
+
+pub struct Template {
+	pub name: String,
+	// the template named by `{% extends "name" %}`, and the line it was on
+	parent: Option<(String, usize)>,
+	nodes: Vec<Node>,
+}
+
+impl Template {
+	/*
+	splits template source into text, `{{ ... }}` tags and `{% ... %}` tags,
+	nesting everything between a `{% block %}` and its `{% endblock %}`
+	*/
+	pub fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+		let error = |line: usize, message: String| TemplateError {
+			template: name.to_string(),
+			line,
+			message,
+		};
+
+		let mut parent = None;
+		// each open tag keeps the nodes that came before it, along with the line it was opened on
+		let mut stack = Vec::<(Open, usize, Vec<Node>)>::new();
+		let mut nodes = Vec::<Node>::new();
+		let mut rest = source;
+		let mut line = 1;
+		// whether `rest` starts at the beginning of a line
+		let mut line_start = true;
+
+		while let Some(start) = next_tag(rest) {
+			let (mut text, tail) = rest.split_at(start);
+			line += text.matches('\n').count();
+			let close = if tail.starts_with("{{") { "}}" } else { "%}" };
+			let mut end = match tail.find(close) {
+				Some(e) => e + 2,
+				None => return Err(error(line, format!("unclosed `{}`", &tail[..2]))),
+			};
+			let raw = &tail[..end];
+			let inner = raw[2..end - 2].trim();
+
+			// a `{% ... %}` tag on a line of its own takes the whole line with it, so it doesn't leave a blank one
+			// (except for `include`, which writes to the page like a `{{ ... }}` tag)
+			let shapes = close == "%}" && inner.split_whitespace().next() != Some("include");
+			let text_line = match text.rfind('\n') {
+				Some(i) => Some(i + 1),
+				None if line_start => Some(0),
+				None => None,
+			};
+			let after = &tail[end..];
+			let after_line = after.find('\n').map(|i| i + 1).unwrap_or(after.len());
+			line_start = false;
+			if let Some(i) = text_line {
+				if shapes && text[i..].trim().is_empty() && after[..after_line].trim().is_empty() {
+					text = &text[..i];
+					end += after_line;
+					line_start = true;
+				}
+			}
+			nodes.push(Node::Text(text.to_string()));
+
+			if close == "}}" {
+				// tags that aren't expressions, like `{{latest 5}}`, are only ever for plugins
+				let expr = tokenize(inner)
+					.ok()
+					.and_then(|t| Parser::new(&t).expr_only());
+				nodes.push(Node::Var {
+					expr,
+					raw: raw.to_string(),
+					line,
+				});
+			} else {
+				let tokens = tokenize(inner).map_err(|e| error(line, e))?;
+				let mut parser = Parser::new(&tokens);
+				let keyword = match tokens.first() {
+					Some(Token::Word(w)) => w.as_str(),
+					_ => "",
+				};
+				parser.pos = 1;
+				match keyword {
+					"extends" => {
+						if !stack.is_empty() {
+							return Err(error(
+								line,
+								String::from("`extends` can't be inside another tag"),
+							));
+						}
+						if parent.is_some() {
+							return Err(error(
+								line,
+								String::from("a template can only extend one other template"),
+							));
+						}
+						let parent_name = parser.name().ok_or_else(|| {
+							error(line, String::from("`extends` needs a template name"))
+						})?;
+						parser.end().map_err(|e| error(line, e))?;
+						parent = Some((template_name(&parent_name), line));
+					}
+					"block" => {
+						let block_name = parser
+							.name()
+							.ok_or_else(|| error(line, String::from("`block` needs a name")))?;
+						parser.end().map_err(|e| error(line, e))?;
+						stack.push((Open::Block(block_name), line, nodes));
+						nodes = Vec::new();
+					}
+					"include" => {
+						let partial = parser.name().ok_or_else(|| {
+							error(line, String::from("`include` needs a partial name"))
+						})?;
+						nodes.push(Node::Include {
+							name: template_name(&partial),
+							line,
+							params: parse_params(&tokens[2..]).map_err(|e| error(line, e))?,
+						});
+					}
+					"if" => {
+						let cond = parser.cond().map_err(|e| error(line, e))?;
+						parser.end().map_err(|e| error(line, e))?;
+						stack.push((Open::If(Vec::new(), Some((cond, line))), line, nodes));
+						nodes = Vec::new();
+					}
+					"for" => {
+						let name = match (parser.next(), parser.next()) {
+							(Some(Token::Word(n)), Some(Token::Word(i))) if i == "in" => n.clone(),
+							_ => {
+								return Err(error(
+									line,
+									String::from("expected `for name in list`"),
+								))
+							}
+						};
+						let list = parser.expr().map_err(|e| error(line, e))?;
+						parser.end().map_err(|e| error(line, e))?;
+						stack.push((Open::For(name, list, None), line, nodes));
+						nodes = Vec::new();
+					}
+					"elif" | "else" => {
+						let cond = match keyword {
+							"elif" => Some((parser.cond().map_err(|e| error(line, e))?, line)),
+							_ => None,
+						};
+						parser.end().map_err(|e| error(line, e))?;
+						match stack.last_mut() {
+							Some((Open::If(branches, current), _, _)) if current.is_some() => {
+								let (done, done_line) = current.take().unwrap();
+								branches.push((done, done_line, nodes));
+								*current = cond;
+							}
+							Some((Open::For(_, _, body), _, _))
+								if body.is_none() && cond.is_none() =>
+							{
+								*body = Some(nodes);
+							}
+							_ => {
+								return Err(error(
+									line,
+									format!("`{}` without an `if` or `for` to go with it", keyword),
+								))
+							}
+						}
+						nodes = Vec::new();
+					}
+					"endblock" | "endif" | "endfor" => {
+						let (open, open_line, outer) = match stack.pop() {
+							Some(o) => o,
+							None => {
+								return Err(error(
+									line,
+									format!("`{}` without anything to close", keyword),
+								))
+							}
+						};
+						let inner_nodes = std::mem::replace(&mut nodes, outer);
+						match (keyword, open) {
+							("endblock", Open::Block(block_name)) => {
+								if let Some(closes) = parser.name() {
+									if closes != block_name {
+										return Err(error(
+											line,
+											format!(
+												"`endblock {}` closes `block {}`",
+												closes, block_name
+											),
+										));
+									}
+								}
+								nodes.push(Node::Block {
+									name: block_name,
+									line: open_line,
+									body: inner_nodes,
+								});
+							}
+							("endif", Open::If(mut branches, current)) => {
+								let otherwise = match current {
+									Some((cond, cond_line)) => {
+										branches.push((cond, cond_line, inner_nodes));
+										Vec::new()
+									}
+									None => inner_nodes,
+								};
+								nodes.push(Node::If {
+									branches,
+									otherwise,
+								});
+							}
+							("endfor", Open::For(name, list, body)) => {
+								let (body, otherwise) = match body {
+									Some(body) => (body, inner_nodes),
+									None => (inner_nodes, Vec::new()),
+								};
+								nodes.push(Node::For {
+									name,
+									list,
+									line: open_line,
+									body,
+									otherwise,
+								});
+							}
+							(_, open) => {
+								return Err(error(
+									line,
+									format!(
+										"`{}` can't close the `{}` from line {}",
+										keyword,
+										open.describe(),
+										open_line
+									),
+								))
+							}
+						}
+						parser.end().map_err(|e| error(line, e))?;
+					}
+					_ => return Err(error(line, format!("unknown tag `{}`", raw))),
+				}
+			}
+
+			line += tail[..end].matches('\n').count();
+			rest = &tail[end..];
+		}
+		nodes.push(Node::Text(rest.to_string()));
+
+		if let Some((open, open_line, _)) = stack.pop() {
+			return Err(error(
+				open_line,
+				format!("`{}` is never closed", open.describe()),
+			));
+		}
+
+		let template = Template {
+			name: name.to_string(),
+			parent,
+			nodes,
+		};
+		// checks for blocks with the same name
+		template.blocks(&mut HashMap::new())?;
+		Ok(template)
+	}
+
+	/*
+	collects every block in this template (including ones nested in other blocks) by name,
+	keeping any that are already there so the most derived template wins
+	*/
+	fn blocks<'a>(
+		&'a self,
+		found: &mut HashMap<String, (&'a Template, &'a [Node])>,
+	) -> Result<(), TemplateError> {
+		let mut own = Vec::<&str>::new();
+		self.collect_blocks(&self.nodes, &mut own, found)
+	}
+
+	fn collect_blocks<'a>(
+		&'a self,
+		nodes: &'a [Node],
+		own: &mut Vec<&'a str>,
+		found: &mut HashMap<String, (&'a Template, &'a [Node])>,
+	) -> Result<(), TemplateError> {
+		for node in nodes {
+			match node {
+				Node::Block { name, line, body } => {
+					if own.contains(&name.as_str()) {
+						return Err(TemplateError {
+							template: self.name.clone(),
+							line: *line,
+							message: format!("`block {}` is defined twice", name),
+						});
+					}
+					own.push(name);
+					found.entry(name.clone()).or_insert((self, body));
+					self.collect_blocks(body, own, found)?;
+				}
+				Node::If {
+					branches,
+					otherwise,
+				} => {
+					for (_, _, body) in branches {
+						self.collect_blocks(body, own, found)?;
+					}
+					self.collect_blocks(otherwise, own, found)?;
+				}
+				Node::For {
+					body, otherwise, ..
+				} => {
+					self.collect_blocks(body, own, found)?;
+					self.collect_blocks(otherwise, own, found)?;
+				}
+				_ => (),
+			}
+		}
+		Ok(())
+	}
+
+	/*
+	writes the nodes to the output, swapping each block for its most derived version
+	and filling in every tag that names a known variable (leaving the rest untouched)
+	*/
+	fn render_nodes(
+		&self,
+		nodes: &[Node],
+		state: &mut Render,
+		scope: &Scope,
+		output: &mut String,
+	) -> Result<(), TemplateError> {
+		let filters = &state.templates.filters;
+		for node in nodes {
+			let error = |line: usize, message: String| TemplateError {
+				template: self.name.clone(),
+				line,
+				message,
+			};
+			match node {
+				Node::Text(text) => output.push_str(text),
+				Node::Var { expr, raw, line } => {
+					let value = match expr {
+						Some(e) => e.eval(scope, filters).map_err(|e| error(*line, e))?,
+						None => None,
+					};
+					match value.as_ref().and_then(display) {
+						Some(value) => push_indented(output, &value),
+						// `page.title` on a page without a title is just empty, since no plugin could be named that
+						None if !bare(expr) => {
+							if let Some(path) = expected(expr) {
+								println!(
+									"warning: {}:{}: `{}` isn't set, so it's empty (`{{{{ {} | default: \"\" }}}}` doesn't warn)",
+									self.name, line, path, path
+								);
+							}
+						}
+						None => output.push_str(raw),
+					}
+				}
+				Node::Block { name, body, .. } => match state.blocks.get(name) {
+					Some((template, nodes)) => {
+						template.render_nodes(nodes, state, scope, output)?
+					}
+					None => self.render_nodes(body, state, scope, output)?,
+				},
+				Node::Include { name, line, params } => {
+					let partial = format!("partials/{}", name);
+					if !state.templates.exists(&partial) {
+						return Err(error(
+							*line,
+							format!(
+								"includes `{}`, but there is no {}",
+								name,
+								state.templates.path(&partial).display()
+							),
+						));
+					}
+					if state.includes.contains(&partial) {
+						return Err(error(*line, format!("`{}` ends up including itself", name)));
+					}
+
+					// parameters are given to the partial as variables of their own
+					let mut partial_vars = Table::new();
+					for (key, arg) in params {
+						let value = arg.resolve(scope).ok_or_else(|| {
+							error(*line, format!("unknown variable given for `{}`", key))
+						})?;
+						partial_vars.insert(key.clone(), value);
+					}
+					let partial_scope = Scope {
+						vars: &partial_vars,
+						parent: Some(scope),
+					};
+
+					state.includes.push(partial.clone());
+					let rendered =
+						state
+							.templates
+							.render_with(&partial, &partial_scope, &state.includes)?;
+					state.includes.pop();
+					// the newline a partial's file ends with would otherwise leave a blank line after it
+					push_indented(output, rendered.strip_suffix('\n').unwrap_or(&rendered));
+				}
+				Node::If {
+					branches,
+					otherwise,
+				} => {
+					let mut chosen = otherwise;
+					for (cond, line, body) in branches {
+						if cond.eval(scope, filters).map_err(|e| error(*line, e))? {
+							chosen = body;
+							break;
+						}
+					}
+					self.render_nodes(chosen, state, scope, output)?;
+				}
+				Node::For {
+					name,
+					list,
+					line,
+					body,
+					otherwise,
+				} => {
+					let items = match list.eval(scope, filters).map_err(|e| error(*line, e))? {
+						None => Vec::new(),
+						Some(Value::Array(a)) => a,
+						// tables are looped over as `key` and `value` pairs
+						Some(Value::Table(t)) => t
+							.into_iter()
+							.map(|(key, value)| {
+								let mut pair = Table::new();
+								pair.insert(String::from("key"), Value::String(key));
+								pair.insert(String::from("value"), value);
+								Value::Table(pair)
+							})
+							.collect(),
+						Some(other) => {
+							return Err(error(*line, format!("can't loop over `{}`", other)))
+						}
+					};
+					if items.is_empty() {
+						self.render_nodes(otherwise, state, scope, output)?;
+					}
+
+					let length = items.len();
+					for (i, item) in items.into_iter().enumerate() {
+						let mut info = Table::new();
+						info.insert(String::from("index"), Value::Integer(i as i64 + 1));
+						info.insert(String::from("index0"), Value::Integer(i as i64));
+						info.insert(String::from("first"), Value::Boolean(i == 0));
+						info.insert(String::from("last"), Value::Boolean(i + 1 == length));
+						info.insert(String::from("length"), Value::Integer(length as i64));
+
+						let mut loop_vars = Table::new();
+						loop_vars.insert(name.clone(), item);
+						loop_vars.insert(String::from("loop"), Value::Table(info));
+						let loop_scope = Scope {
+							vars: &loop_vars,
+							parent: Some(scope),
+						};
+						self.render_nodes(body, state, &loop_scope, output)?;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+// what's needed while rendering, on top of the variables
+struct Render<'a> {
+	templates: &'a Templates,
+	// the most derived version of each block
+	blocks: HashMap<String, (&'a Template, &'a [Node])>,
+	// the partials being included right now, so one that includes itself can be stopped
+	includes: Vec<String>,
+}
+
+/*
+loads templates from the template directory by name (`default` is `default.html`, or `default.gn`),
+keeping each one after it's been parsed so it's only read once per build
+*/
+pub struct Templates {
+	dir: String,
+	cache: RefCell<HashMap<String, Rc<Template>>>,
+	// the filters templates can use, which more can be registered into,
+	// with dates in utc until they're replaced with ones at the site's offset
+	pub filters: Filters,
+}
+
+impl Templates {
+	pub fn new(dir: &str) -> Templates {
+		Templates {
+			dir: dir.to_string(),
+			cache: RefCell::new(HashMap::new()),
+			filters: Filters::default(),
+		}
+	}
+
+	pub fn exists(&self, name: &str) -> bool {
+		self.cache.borrow().contains_key(&template_name(name)) || self.path(name).is_file()
+	}
+
+	/*
+	the file a template is read from, an html template if there is one and a granite one if not
+	when neither exists, this is the html path, so errors can say what's missing
+	*/
+	pub fn path(&self, name: &str) -> std::path::PathBuf {
+		let dir = std::path::Path::new(&self.dir);
+		let name = template_name(name);
+		let granite = dir.join(format!("{}.gn", name));
+		let html = dir.join(format!("{}.html", name));
+		if !html.is_file() && granite.is_file() {
+			granite
+		} else {
+			html
+		}
+	}
+
+	pub fn load(&self, name: &str) -> Result<Rc<Template>, TemplateError> {
+		let name = template_name(name);
+		if let Some(template) = self.cache.borrow().get(&name) {
+			return Ok(template.clone());
+		}
+
+		let path = self.path(&name);
+		let source = fs::read_to_string(&path).map_err(|e| TemplateError {
+			template: name.clone(),
+			line: 0,
+			message: format!("couldn't read {}: {}", path.display(), e),
+		})?;
+		let template = match path.extension().and_then(|e| e.to_str()) {
+			Some("gn") => from_granite(&name, &source)?,
+			_ => Template::parse(&name, &source)?,
+		};
+		let template = Rc::new(template);
+		self.cache.borrow_mut().insert(name, template.clone());
+		Ok(template)
+	}
+
+	/*
+	follows `{% extends %}` from the named template up to the one that extends nothing,
+	returning the chain with the named template first
+	*/
+	fn chain(&self, name: &str) -> Result<Vec<Rc<Template>>, TemplateError> {
+		let mut chain = vec![self.load(name)?];
+		loop {
+			let child = chain.last().unwrap().clone();
+			let (parent, line) = match &child.parent {
+				Some(p) => p,
+				None => return Ok(chain),
+			};
+			if chain.iter().any(|t| &t.name == parent) {
+				let mut names = chain.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+				names.push(parent.clone());
+				return Err(TemplateError {
+					template: child.name.clone(),
+					line: *line,
+					message: format!(
+						"templates extend each other in a loop: {}",
+						names.join(" -> ")
+					),
+				});
+			}
+			if !self.exists(parent) {
+				return Err(TemplateError {
+					template: child.name.clone(),
+					line: *line,
+					message: format!(
+						"extends `{}`, but there is no {}",
+						parent,
+						self.path(parent).display()
+					),
+				});
+			}
+			chain.push(self.load(parent)?);
+		}
+	}
+
+	/*
+	renders the named template with the given variables, after resolving what it extends
+	*/
+	pub fn render(&self, name: &str, vars: &Table) -> Result<String, TemplateError> {
+		let scope = Scope { vars, parent: None };
+		self.render_with(name, &scope, &[])
+	}
+
+	fn render_with(
+		&self,
+		name: &str,
+		scope: &Scope,
+		includes: &[String],
+	) -> Result<String, TemplateError> {
+		let chain = self.chain(name)?;
+		let mut blocks = HashMap::new();
+		for template in &chain {
+			template.blocks(&mut blocks)?;
+		}
+
+		let mut state = Render {
+			templates: self,
+			blocks,
+			includes: includes.to_vec(),
+		};
+		let base = chain.last().unwrap();
+		let mut output = String::new();
+		base.render_nodes(&base.nodes, &mut state, scope, &mut output)?;
+		Ok(output)
+	}
+}
+
+// finds where the next `{{` or `{%` tag starts
+fn next_tag(s: &str) -> Option<usize> {
+	match (s.find("{{"), s.find("{%")) {
+		(Some(a), Some(b)) => Some(a.min(b)),
+		(a, b) => a.or(b),
+	}
+}
+
+// template names are given without the .html or .gn extension, but it's stripped here if it's there
+fn template_name(name: &str) -> String {
+	let name = name.strip_suffix(".html").unwrap_or(name);
+	name.strip_suffix(".gn").unwrap_or(name).to_string()
+}
+
+/*
+parses a template written in granite, like a page is
+`{{ }}` and `{% %}` tags are swapped for placeholders while granite runs so it leaves them alone,
+and an empty `[content|]` element is the slot the page goes in, the same as `{{content}}`
+granite keeps every line where it was, so errors still point at the right line of the .gn file
+*/
+fn from_granite(name: &str, source: &str) -> Result<Template, TemplateError> {
+	let placeholder = |i: usize| format!("\u{E000}{}\u{E001}", i);
+	let mut tags = Vec::new();
+	let mut protected = String::new();
+	let mut rest = source;
+	while let Some(start) = next_tag(rest) {
+		let close = if rest[start..].starts_with("{{") {
+			"}}"
+		} else {
+			"%}"
+		};
+		let end = match rest[start + 2..].find(close) {
+			Some(end) => start + 2 + end + 2,
+			// the template parser gives a better error for this than we could
+			None => break,
+		};
+		let tag = &rest[start..end];
+		protected.push_str(&rest[..start]);
+		protected.push_str(&placeholder(tags.len()));
+		// newlines inside a tag are kept outside of its placeholder, so lines don't move
+		protected.push_str(&"\n".repeat(tag.matches('\n').count()));
+		tags.push(tag.replace('\n', " "));
+		rest = &rest[end..];
+	}
+	protected.push_str(rest);
+
+	// -> granite.rs:[parse(s: &str)]
+	let page = parse(&protected);
+	// a meta header has no use in a template, and would take lines away from the rest of it
+	if !page.meta.is_empty() {
+		return Err(TemplateError {
+			template: name.to_string(),
+			line: 1,
+			message: String::from("granite templates can't have a !meta! header"),
+		});
+	}
+	let mut html = page.content;
+	for (i, tag) in tags.iter().enumerate() {
+		html = html.replacen(&placeholder(i), tag, 1);
+	}
+	let html = CONTENT_SLOT.replace_all(&html, "{{content}}");
+	Template::parse(name, &html)
+}
+
+lazy_static! {
+	static ref CONTENT_SLOT: Regex = Regex::new(r"<content>\s*</content>").unwrap();
+}
+
+/*
+splits the inside of a tag into quoted strings, words and punctuation
+*/
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+	let mut tokens = Vec::<Token>::new();
+	let mut chars = s.chars().peekable();
+	while let Some(&c) = chars.peek() {
+		match c {
+			' ' | '\t' | '\n' | '\r' => {
+				chars.next();
+			}
+			'"' | '\'' => {
+				chars.next();
+				let mut string = String::new();
+				loop {
+					match chars.next() {
+						Some('\\') => string.extend(chars.next()),
+						Some(q) if q == c => break,
+						Some(other) => string.push(other),
+						None => return Err(format!("unclosed string `{}{}`", c, string)),
+					}
+				}
+				tokens.push(Token::Str(string));
+			}
+			_ if SYMBOLS.iter().any(|sym| sym.starts_with(c)) => {
+				let rest = chars.clone().collect::<String>();
+				let sym = match SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+					Some(sym) => sym,
+					None => return Err(format!("unexpected `{}`", c)),
+				};
+				for _ in 0..sym.len() {
+					chars.next();
+				}
+				tokens.push(Token::Sym(sym));
+			}
+			_ => {
+				let mut word = String::new();
+				while let Some(&w) = chars.peek() {
+					if w.is_alphanumeric() || "_-./".contains(w) {
+						word.push(w);
+						chars.next();
+					} else {
+						break;
+					}
+				}
+				if word.is_empty() {
+					return Err(format!("unexpected `{}`", c));
+				}
+				tokens.push(Token::Word(word));
+			}
+		}
+	}
+	Ok(tokens)
+}
+
+// words which can't be a variable or a filter argument, since they're part of a condition
+const KEYWORDS: [&str; 4] = ["and", "or", "not", "in"];
+
+/*
+reads expressions and conditions from a tag's tokens
+*/
+struct Parser<'a> {
+	tokens: &'a [Token],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn new(tokens: &'a [Token]) -> Parser<'a> {
+		Parser { tokens, pos: 0 }
+	}
+
+	fn peek(&self) -> Option<&'a Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<&'a Token> {
+		let token = self.tokens.get(self.pos);
+		self.pos += 1;
+		token
+	}
+
+	fn is_sym(&self, sym: &str) -> bool {
+		matches!(self.peek(), Some(Token::Sym(s)) if *s == sym)
+	}
+
+	fn is_word(&self, word: &str) -> bool {
+		matches!(self.peek(), Some(Token::Word(w)) if w == word)
+	}
+
+	// errors if there's anything left over
+	fn end(&self) -> Result<(), String> {
+		match self.peek() {
+			Some(token) => Err(format!("unexpected `{}`", token)),
+			None => Ok(()),
+		}
+	}
+
+	fn name(&mut self) -> Option<String> {
+		let name = name_arg(self.peek()?)?;
+		self.pos += 1;
+		Some(name)
+	}
+
+	// a value that isn't a keyword
+	fn value(&mut self) -> Option<Arg> {
+		match self.peek()? {
+			Token::Word(w) if KEYWORDS.contains(&w.as_str()) => None,
+			token => {
+				let arg = value_arg(token)?;
+				self.pos += 1;
+				Some(arg)
+			}
+		}
+	}
+
+	/*
+	expr := ( "url_for" value | value ) ( "|" filter value* )*
+	*/
+	fn expr(&mut self) -> Result<Expr, String> {
+		let helper = matches!(
+			self.tokens.get(self.pos + 1),
+			Some(Token::Str(_)) | Some(Token::Word(_))
+		);
+		let value = match self.value() {
+			Some(Arg::Path(name)) if name == "url_for" && helper => match self.value() {
+				Some(path) => Arg::Url(Box::new(path)),
+				None => return Err(String::from("expected a path after `url_for`")),
+			},
+			Some(v) => v,
+			None => match self.peek() {
+				Some(token) => return Err(format!("expected a value, found `{}`", token)),
+				None => return Err(String::from("expected a value")),
+			},
+		};
+		let mut filters = Vec::new();
+		while self.is_sym("|") {
+			self.pos += 1;
+			let name = match self.next() {
+				Some(Token::Word(w)) => w.clone(),
+				_ => return Err(String::from("expected a filter name after `|`")),
+			};
+			let mut args = Vec::new();
+			while let Some(arg) = self.value() {
+				args.push(arg);
+			}
+			filters.push((name, args));
+		}
+		Ok(Expr { value, filters })
+	}
+
+	// an expression which is the whole tag, or nothing if it isn't one
+	fn expr_only(&mut self) -> Option<Expr> {
+		let expr = self.expr().ok()?;
+		self.end().ok()?;
+		Some(expr)
+	}
+
+	/*
+	cond := and ( "or" and )*
+	and  := not ( "and" not )*
+	not  := "not" not | "(" cond ")" | expr ( op expr )?
+	*/
+	fn cond(&mut self) -> Result<Cond, String> {
+		let mut cond = self.and()?;
+		while self.is_word("or") {
+			self.pos += 1;
+			cond = Cond::Or(Box::new(cond), Box::new(self.and()?));
+		}
+		Ok(cond)
+	}
+
+	fn and(&mut self) -> Result<Cond, String> {
+		let mut cond = self.not()?;
+		while self.is_word("and") {
+			self.pos += 1;
+			cond = Cond::And(Box::new(cond), Box::new(self.not()?));
+		}
+		Ok(cond)
+	}
+
+	fn not(&mut self) -> Result<Cond, String> {
+		if self.is_word("not") {
+			self.pos += 1;
+			return Ok(Cond::Not(Box::new(self.not()?)));
+		}
+		if self.is_sym("(") {
+			self.pos += 1;
+			let cond = self.cond()?;
+			if !self.is_sym(")") {
+				return Err(String::from("expected `)`"));
+			}
+			self.pos += 1;
+			return Ok(cond);
+		}
+
+		let left = self.expr()?;
+		let op = match self.peek() {
+			Some(Token::Sym(op)) if ["==", "!=", "<", ">", "<=", ">="].contains(op) => *op,
+			Some(Token::Word(w)) if w == "in" => "in",
+			_ => return Ok(Cond::Test(left)),
+		};
+		self.pos += 1;
+		Ok(Cond::Compare(left, op, self.expr()?))
+	}
+}
+
+// a name given to a tag, either quoted or not
+fn name_arg(token: &Token) -> Option<String> {
+	match token {
+		Token::Str(s) | Token::Word(s) => Some(s.clone()),
+		Token::Sym(_) => None,
+	}
+}
+
+// a written out value or a variable path
+fn value_arg(token: &Token) -> Option<Arg> {
+	match token {
+		Token::Str(s) => Some(Arg::Value(Value::String(s.clone()))),
+		Token::Word(w) => Some(match w.as_str() {
+			"true" => Arg::Value(Value::Boolean(true)),
+			"false" => Arg::Value(Value::Boolean(false)),
+			_ => match (w.parse::<i64>(), w.parse::<f64>()) {
+				(Ok(i), _) => Arg::Value(Value::Integer(i)),
+				(_, Ok(f)) => Arg::Value(Value::Float(f)),
+				_ => Arg::Path(w.clone()),
+			},
+		}),
+		Token::Sym(_) => None,
+	}
+}
+
+/*
+parses `key: value` parameters, optionally separated by commas
+*/
+fn parse_params(tokens: &[Token]) -> Result<Vec<(String, Arg)>, String> {
+	let mut params = Vec::new();
+	let mut rest = tokens;
+	loop {
+		match rest {
+			[] => return Ok(params),
+			[Token::Sym(","), tail @ ..] => rest = tail,
+			[Token::Word(key), Token::Sym(":"), value, tail @ ..] => {
+				let value = value_arg(value).ok_or_else(|| format!("`{}` needs a value", key))?;
+				params.push((key.clone(), value));
+				rest = tail;
+			}
+			[other, ..] => return Err(format!("expected `name: value`, found `{}`", other)),
+		}
+	}
+}
+
+/*
+finds a dotted variable path (`page.title`, `site.latest_length`) in the given variables
+*/
+pub fn lookup<'a>(vars: &'a Table, path: &str) -> Option<&'a Value> {
+	let mut parts = path.split('.');
+	let mut value = vars.get(parts.next()?)?;
+	for part in parts {
+		value = match value {
+			Value::Table(t) => t.get(part)?,
+			Value::Array(a) => a.get(part.parse::<usize>().ok()?)?,
+			_ => return None,
+		};
+	}
+	Some(value)
+}
+
+/*
+turns a variable into the text that ends up in the page, tables and arrays have no sensible text form
+*/
+fn display(value: &Value) -> Option<String> {
+	match value {
+		Value::String(s) => Some(s.clone()),
+		Value::Integer(i) => Some(i.to_string()),
+		Value::Float(f) => Some(f.to_string()),
+		Value::Boolean(b) => Some(b.to_string()),
+		Value::Datetime(d) => Some(d.to_string()),
+		Value::Array(_) | Value::Table(_) => None,
+	}
+}
+
+// elements whose text has to stay exactly as it is, so their lines are never re-indented
+const VERBATIM: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/*
+adds text that may span several lines to the output
+when it's the first thing on its line, like an indented `{{content}}`, every line after the first
+is given the same indentation, except lines inside a `VERBATIM` element or ones that are empty
+*/
+fn push_indented(output: &mut String, text: &str) {
+	let line_start = output.rfind('\n').map(|i| i + 1).unwrap_or(0);
+	let indent = output[line_start..].to_string();
+	if indent.is_empty() || !indent.chars().all(|c| c == ' ' || c == '\t') {
+		output.push_str(text);
+		return;
+	}
+
+	let mut open = 0;
+	for (i, line) in text.split('\n').enumerate() {
+		if i > 0 {
+			output.push('\n');
+			if open == 0 && !line.is_empty() {
+				output.push_str(&indent);
+			}
+		}
+		output.push_str(line);
+		open = (open + verbatim_depth(line)).max(0);
+	}
+}
+
+// how many verbatim elements a line opens, minus how many it closes
+fn verbatim_depth(line: &str) -> i32 {
+	let lower = line.to_lowercase();
+	let mut depth = 0;
+	for name in VERBATIM.iter() {
+		for (i, _) in lower.match_indices(&format!("<{}", name)) {
+			// `<pre>` or `<pre class="...">`, but not `<preview>`
+			match lower[i + name.len() + 1..].chars().next() {
+				Some('>') | Some(' ') | Some('\t') | None => depth += 1,
+				_ => (),
+			}
+		}
+		depth -= lower.matches(&format!("</{}>", name)).count() as i32;
+	}
+	depth
+}
+
+/*
+picks the template a page is built with, and why it was picked
+the page's `template` meta variable comes first, then the most specific glob in the [templates]
+config table that matches the page's path (relative to the granite directory), and then `default`
+a template that doesn't exist is skipped over, and the reason says so
+*/
+pub fn choose(config: &Config, templates: &Templates, source: &Source) -> (String, String) {
+	let mut skipped = Vec::new();
+	if let Some(name) = source.meta("template") {
+		if templates.exists(name) {
+			return (
+				name.to_string(),
+				String::from("`template` in the page's meta header"),
+			);
+		}
+		skipped.push(format!("the meta header's `{}` doesn't exist", name));
+	}
+
+	let path = source
+		.path
+		.strip_prefix(&config.granite_path)
+		.unwrap_or(&source.path);
+	let mut globs = config
+		.templates
+		.iter()
+		.filter(|(glob, _)| glob::matches(glob, path))
+		.collect::<Vec<_>>();
+	// most specific first
+	globs.sort_by_key(|(glob, _)| std::cmp::Reverse(glob::specificity(glob)));
+	for (glob, name) in globs {
+		if templates.exists(name) {
+			let mut reason = format!("matches \"{}\" in [templates]", glob);
+			if !skipped.is_empty() {
+				reason.push_str(&format!(", because {}", skipped.join(" and ")));
+			}
+			return (name.clone(), reason);
+		}
+		skipped.push(format!(
+			"\"{}\" maps to `{}`, which doesn't exist",
+			glob, name
+		));
+	}
+
+	let reason = if skipped.is_empty() {
+		String::from("no `template` meta variable or [templates] glob for this page")
+	} else {
+		skipped.join(" and ")
+	};
+	(String::from("default"), reason)
+}
+
+/*
+builds the variables a page's template can use:
+everything from `site::globals`, `page.*` for the page's meta header (and where it is),
+`build.*` for information about the current run, `root` for the way back to the top of the site,
+and `content` for the parsed page
+*/
+pub fn context(config: &Config, globals: &Table, source: &Source, page: &Page) -> Table {
+	let mut build = Table::new();
+	build.insert(String::from("source"), Value::String(source.path.clone()));
+	build.insert(String::from("output"), Value::String(source.target.clone()));
+	build.insert(
+		String::from("time"),
+		Value::Integer(config.build_time as i64),
+	);
+
+	let root = source.root();
+	let mut vars = globals.clone();
+	vars.insert(String::from("page"), Value::Table(source.vars()));
+	vars.insert(String::from("build"), Value::Table(build));
+	vars.insert(
+		String::from("content"),
+		Value::String(site::links(&page.content, &root)),
+	);
+	vars.insert(String::from("root"), Value::String(root));
+	vars
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn vars() -> Table {
+		let mut page = Table::new();
+		page.insert(String::from("title"), Value::String(String::from("Home")));
+		let mut site = Table::new();
+		site.insert(String::from("latest_length"), Value::Integer(15));
+		let mut vars = Table::new();
+		vars.insert(String::from("page"), Value::Table(page));
+		vars.insert(String::from("site"), Value::Table(site));
+		vars.insert(
+			String::from("content"),
+			Value::String(String::from("<p>hi</p>")),
+		);
+		vars
+	}
+
+	// a template set that only has the given templates, without touching the template directory
+	fn templates(sources: &[(&str, &str)]) -> Templates {
+		let templates = Templates::new("");
+		for (name, source) in sources {
+			let template = Template::parse(name, source).unwrap();
+			templates
+				.cache
+				.borrow_mut()
+				.insert(name.to_string(), Rc::new(template));
+		}
+		templates
+	}
+
+	#[test]
+	fn test_render_variables() {
+		let t = templates(&[(
+			"t",
+			"<title>{{ page.title }}</title>{{content}} {{site.latest_length}}",
+		)]);
+		let expect = String::from("<title>Home</title><p>hi</p> 15");
+		assert_eq!(expect, t.render("t", &vars()).unwrap());
+	}
+
+	#[test]
+	fn test_unknown_falls_through() {
+		let t = templates(&[(
+			"t",
+			"{{date}} [{{page.missing}}] {{site}} [{{ build.nothing }}]",
+		)]);
+		let expect = String::from("{{date}} [] {{site}} []");
+		assert_eq!(expect, t.render("t", &vars()).unwrap());
+	}
+
+	#[test]
+	fn test_expected() {
+		let expr = |s: &str| {
+			Some(Expr {
+				value: Arg::Path(s.to_string()),
+				filters: Vec::new(),
+			})
+		};
+		assert_eq!(Some("page.missing"), expected(&expr("page.missing")));
+		assert_eq!(Some("site.name"), expected(&expr("site.name")));
+		assert_eq!(None, expected(&expr("build.nothing")));
+		assert_eq!(None, expected(&expr("latest")));
+	}
+
+	#[test]
+	fn test_unclosed_tag() {
+		let err = Template::parse("t", "<p>\n{{content</p>").err().unwrap();
+		assert_eq!(2, err.line);
+	}
+
+	#[test]
+	fn test_extends() {
+		let t = templates(&[
+			(
+				"base",
+				"<title>{% block title %}Site{% endblock %}</title>{% block main %}{% endblock %}|{% block footer %}foot{% endblock %}",
+			),
+			(
+				"page",
+				"{% extends \"base\" %}{% block main %}<main>{{content}}</main>{% endblock %}",
+			),
+			(
+				"post",
+				"{% extends \"page\" %}{% block title %}{{page.title}}{% endblock title %}",
+			),
+		]);
+		let expect = String::from("<title>Home</title><main><p>hi</p></main>|foot");
+		assert_eq!(expect, t.render("post", &vars()).unwrap());
+	}
+
+	#[test]
+	fn test_extends_errors() {
+		let t = templates(&[
+			("a", "{% extends \"b\" %}"),
+			("b", "\n{% extends \"a\" %}"),
+			("c", "{% extends \"missing\" %}"),
+		]);
+		let err = t.render("a", &vars()).err().unwrap();
+		assert_eq!(("b", 2), (err.template.as_str(), err.line));
+		assert!(err.message.contains("a -> b -> a"));
+
+		let err = t.render("c", &vars()).err().unwrap();
+		assert!(err.message.contains("`missing`"));
+
+		let err = Template::parse(
+			"d",
+			"{% block x %}\n{% block x %}{% endblock %}{% endblock %}",
+		)
+		.err()
+		.unwrap();
+		assert_eq!(2, err.line);
+		assert!(Template::parse("e", "{% block x %}").is_err());
+	}
+
+	#[test]
+	fn test_include() {
+		let t = templates(&[
+			(
+				"page",
+				"{% include \"nav\" active: \"home\", title: page.title %}|{% include footer %}",
+			),
+			("partials/nav", "<a class=\"{{active}}\">{{title}}</a>"),
+			("partials/footer", "{{site.latest_length}}"),
+		]);
+		let expect = String::from("<a class=\"home\">Home</a>|15");
+		assert_eq!(expect, t.render("page", &vars()).unwrap());
+	}
+
+	#[test]
+	fn test_include_errors() {
+		let t = templates(&[
+			("page", "<p>\n{% include \"missing\" %}"),
+			("loop", "{% include \"again\" %}"),
+			("partials/again", "{% include \"again\" %}"),
+		]);
+		let err = t.render("page", &vars()).err().unwrap();
+		assert_eq!(("page", 2), (err.template.as_str(), err.line));
+		assert!(err.message.contains("`missing`"));
+		assert!(t.render("loop", &vars()).is_err());
+		assert!(Template::parse("bad", "{% include \"nav\" active %}").is_err());
+	}
+
+	#[test]
+	fn test_if() {
+		let t = templates(&[(
+			"t",
+			"{% if page.title == \"About\" %}a{% elif not page.missing and site.latest_length > 10 %}b{% else %}c{% endif %}",
+		)]);
+		assert_eq!("b", t.render("t", &vars()).unwrap());
+
+		let err = Template::parse("bad", "\n{% if page.title == %}{% endif %}")
+			.err()
+			.unwrap();
+		assert_eq!(2, err.line);
+		assert!(Template::parse("bad", "{% else %}").is_err());
+		assert!(Template::parse("bad", "{% if x %}{% endfor %}").is_err());
+	}
+
+	#[test]
+	fn test_for() {
+		let mut vars = vars();
+		vars.insert(
+			String::from("pages"),
+			Value::Array(vec![vars["page"].clone(), vars["page"].clone()]),
+		);
+		vars.insert(String::from("none"), Value::Array(Vec::new()));
+		let t = templates(&[
+			(
+				"t",
+				"{% for p in pages %}{{loop.index}}:{{p.title}}{% if not loop.last %},{% endif %}{% endfor %}",
+			),
+			("empty", "{% for p in none %}{{p}}{% else %}nothing{% endfor %}"),
+		]);
+		assert_eq!("1:Home,2:Home", t.render("t", &vars).unwrap());
+		assert_eq!("nothing", t.render("empty", &vars).unwrap());
+	}
+
+	#[test]
+	fn test_filters() {
+		let t = templates(&[
+			(
+				"t",
+				"{{ page.title | upper }} {{ page.missing | default \"none\" }} {{ content | escape }}",
+			),
+			("bad", "\n{{ page.title | nope }}"),
+		]);
+		assert_eq!(
+			"HOME none &lt;p&gt;hi&lt;/p&gt;",
+			t.render("t", &vars()).unwrap()
+		);
+		let err = t.render("bad", &vars()).err().unwrap();
+		assert_eq!(2, err.line);
+		assert!(err.message.contains("`nope`"));
+	}
+
+	#[test]
+	fn test_granite_template() {
+		let source = "[html|\n\t[head|[title|{{ page.title | upper }}]]\n\t[body class: \"page\"|\n\t\t{% if page.title %}[h1|{{page.title}}]{% endif %}\n\t\t[content|]\n\t]\n]\n";
+		let t = templates(&[]);
+		let template = from_granite("t", source).unwrap();
+		t.cache
+			.borrow_mut()
+			.insert(String::from("t"), Rc::new(template));
+		let expect = "<html>\n\t<head><title>HOME</title></head>\n\t<body class=\"page\">\n\t\t<h1>Home</h1>\n\t\t<p>hi</p>\n\t</body>\n</html>\n";
+		assert_eq!(expect, t.render("t", &vars()).unwrap());
+
+		let template = from_granite("bad", "[div|\n\t[p|{{ page.title | nope }}]\n]\n").unwrap();
+		t.cache
+			.borrow_mut()
+			.insert(String::from("bad"), Rc::new(template));
+		let err = t.render("bad", &vars()).err().unwrap();
+		assert_eq!(2, err.line);
+		assert!(from_granite("meta", "!meta!\ntitle: hi\n!meta!\n[content|]").is_err());
+	}
+
+	#[test]
+	fn test_content_indentation() {
+		let mut vars = vars();
+		vars.insert(
+			String::from("content"),
+			Value::String(String::from(
+				"<p>one</p>\n\n<pre>\nkeep\n  this\n</pre>\n<p>two</p>",
+			)),
+		);
+		let t = templates(&[("t", "<main>\n\t\t{{content}}\n</main> <p>{{content}}</p>")]);
+		let expect = "<main>\n\t\t<p>one</p>\n\n\t\t<pre>\nkeep\n  this\n</pre>\n\t\t<p>two</p>\n</main> <p><p>one</p>\n\n<pre>\nkeep\n  this\n</pre>\n<p>two</p></p>";
+		assert_eq!(expect, t.render("t", &vars).unwrap());
+	}
+
+	#[test]
+	fn test_url_for() {
+		let mut vars = vars();
+		vars.insert(String::from("root"), Value::String(String::from("../")));
+		let t = templates(&[(
+			"t",
+			"{{root}}style.css {{ url_for \"notes/uxn.gn\" }} {{ url_for page.title | lower }}",
+		)]);
+		assert_eq!(
+			"../style.css ../notes/uxn.html ../home",
+			t.render("t", &vars).unwrap()
+		);
+	}
+}
@@ -6,8 +6,8 @@ pub mod text {
 	removes whitespace around the given string from start and end offsets
 	returns the trimmed string and the indexes of its start and end
 	*/
-	pub fn trim(l: &String, start: usize, end: usize) -> (String, usize, usize) {
-		let mut line = l.clone();
+	pub fn trim(l: &str, start: usize, end: usize) -> (String, usize, usize) {
+		let mut line = l.to_string();
 		let mut first: usize = 0;
 		let mut last: usize = 0;
 		let mut hit_text = false;
@@ -41,6 +41,7 @@ pub mod text {
 	/*
 	replaces all target str in String with insert str
 	*/
+	#[allow(dead_code)]
 	pub fn replace(s: &str, target: &str, insert: &str) -> String {
 		let mut out = s.to_string();
 		while let Some(i) = out.find(target) {
@@ -56,14 +57,13 @@ pub mod text {
 		assert!(i <= len(s), "the index was larger than the target slice");
 
 		let first = slice(s, 0..i);
-		let second: String;
 		// if this is met, the range is len..len, which rust evaluate to the whole string
-		if i+l == len(s) {
+		let second = if i + l == len(s) {
 			// so in this condition we zero out the second half
-			second = String::from("");
+			String::from("")
 		} else {
-			second = slice(s, i + l..len(s));
-		}
+			slice(s, i + l..len(s))
+		};
 
 		[first, second].concat()
 	}
@@ -131,6 +131,312 @@ pub mod text {
 	}
 }
 
+/*  dates without a date library  */
+pub mod date {
+
+	const MONTHS: [&str; 12] = [
+		"January",
+		"February",
+		"March",
+		"April",
+		"May",
+		"June",
+		"July",
+		"August",
+		"September",
+		"October",
+		"November",
+		"December",
+	];
+	const DAYS: [&str; 7] = [
+		"Sunday",
+		"Monday",
+		"Tuesday",
+		"Wednesday",
+		"Thursday",
+		"Friday",
+		"Saturday",
+	];
+
+	/*
+	turns days since 1970-01-01 into a (year, month, day), using Howard Hinnant's civil_from_days
+	*/
+	pub fn civil(days: i64) -> (i64, u32, u32) {
+		let z = days + 719_468;
+		let era = z.div_euclid(146_097);
+		let doe = z.rem_euclid(146_097);
+		let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+		let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+		let mp = (5 * doy + 2) / 153;
+		let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+		let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+		(year, month, day)
+	}
+
+	/*
+	turns a (year, month, day) into days since 1970-01-01, the reverse of `civil`
+	*/
+	pub fn days(year: i64, month: u32, day: u32) -> i64 {
+		let year = if month <= 2 { year - 1 } else { year };
+		let era = year.div_euclid(400);
+		let yoe = year.rem_euclid(400);
+		let mp = (month as i64 + 9) % 12;
+		let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+		let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+		era * 146_097 + doe - 719_468
+	}
+
+	/*
+	reads a date like `2023-05-09`, `2023-05-09 14:30` or `2023-05-09T14:30:00Z` into seconds since the unix epoch
+	times without an offset are taken to be utc
+	*/
+	pub fn parse(s: &str) -> Option<i64> {
+		let s = s.trim();
+		let (date, time) = match s.find(['T', ' ']) {
+			Some(i) => (&s[..i], s[i + 1..].trim()),
+			None => (s, ""),
+		};
+
+		let mut parts = date.split('-');
+		let year = parts.next()?.parse::<i64>().ok()?;
+		let month = parts.next()?.parse::<u32>().ok()?;
+		let day = parts.next()?.parse::<u32>().ok()?;
+		if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+			return None;
+		}
+
+		// splits any `Z` or `+hh:mm` offset off of the time
+		let (time, offset) = match time.find(['Z', '+', '-']) {
+			Some(i) => (&time[..i], parse_offset(&time[i..])?),
+			None => (time, 0),
+		};
+		let mut seconds = 0;
+		if !time.is_empty() {
+			let mut units = time.split(':');
+			seconds += units.next()?.parse::<i64>().ok()? * 3600;
+			seconds += units.next()?.parse::<i64>().ok()? * 60;
+			if let Some(s) = units.next() {
+				seconds += s.split('.').next()?.parse::<i64>().ok()?;
+			}
+		}
+
+		Some(days(year, month, day) * 86400 + seconds - offset)
+	}
+
+	// whether a date like `2023-05-09T14:30:00+02:00` says which timezone it's in
+	pub fn has_offset(d: &str) -> bool {
+		match d.trim().find(['T', ' ']) {
+			Some(i) => d.trim()[i + 1..].contains(['Z', '+', '-']),
+			None => false,
+		}
+	}
+
+	/*
+	reads a utc offset like `Z`, `+02:00`, `-0530` or `+2` into seconds
+	*/
+	pub fn parse_offset(s: &str) -> Option<i64> {
+		let s = s.trim();
+		if s == "Z" || s.eq_ignore_ascii_case("utc") {
+			return Some(0);
+		}
+		let sign = match s.chars().next()? {
+			'+' => 1,
+			'-' => -1,
+			_ => return None,
+		};
+		let digits = s[1..].replace(':', "");
+		let (hours, minutes) = match digits.len() {
+			1 | 2 => (digits.parse::<i64>().ok()?, 0),
+			4 => (
+				digits[..2].parse::<i64>().ok()?,
+				digits[2..].parse::<i64>().ok()?,
+			),
+			_ => return None,
+		};
+		Some(sign * (hours * 3600 + minutes * 60))
+	}
+
+	/*
+	formats seconds since the unix epoch with strftime style specifiers:
+	%Y %y %m %d %e %H %I %M %S %p %B %b %A %a %j %F %T %s and %%
+	*/
+	pub fn format(secs: i64, fmt: &str) -> String {
+		let days = secs.div_euclid(86400);
+		let time = secs.rem_euclid(86400);
+		let (year, month, day) = civil(days);
+		let (hour, minute, second) = (time / 3600, time / 60 % 60, time % 60);
+		let weekday = (days + 4).rem_euclid(7) as usize;
+		let month_name = MONTHS[month as usize - 1];
+
+		let mut out = String::new();
+		let mut chars = fmt.chars();
+		while let Some(c) = chars.next() {
+			if c != '%' {
+				out.push(c);
+				continue;
+			}
+			match chars.next() {
+				Some('Y') => out.push_str(&year.to_string()),
+				Some('y') => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+				Some('m') => out.push_str(&format!("{:02}", month)),
+				Some('d') => out.push_str(&format!("{:02}", day)),
+				Some('e') => out.push_str(&day.to_string()),
+				Some('H') => out.push_str(&format!("{:02}", hour)),
+				Some('I') => out.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+				Some('M') => out.push_str(&format!("{:02}", minute)),
+				Some('S') => out.push_str(&format!("{:02}", second)),
+				Some('p') => out.push_str(if hour < 12 { "AM" } else { "PM" }),
+				Some('B') => out.push_str(month_name),
+				Some('b') => out.push_str(&month_name[..3]),
+				Some('A') => out.push_str(DAYS[weekday]),
+				Some('a') => out.push_str(&DAYS[weekday][..3]),
+				Some('j') => out.push_str(&format!("{:03}", days - self::days(year, 1, 1) + 1)),
+				Some('F') => out.push_str(&format(secs, "%Y-%m-%d")),
+				Some('T') => out.push_str(&format(secs, "%H:%M:%S")),
+				Some('s') => out.push_str(&secs.to_string()),
+				Some('%') => out.push('%'),
+				Some(other) => {
+					out.push('%');
+					out.push(other);
+				}
+				None => out.push('%'),
+			}
+		}
+		out
+	}
+}
+
+// path globs, like `notes/**` or `*.gn`
+pub mod glob {
+	// `*` matches anything within one directory, `?` matches one character,
+	// and `**` matches any number of directories (so `notes/**` is everything under notes/)
+	pub fn matches(pattern: &str, path: &str) -> bool {
+		let pattern = pattern.chars().collect::<Vec<_>>();
+		let path = path.chars().collect::<Vec<_>>();
+		match_from(&pattern, &path)
+	}
+
+	fn match_from(p: &[char], s: &[char]) -> bool {
+		match p {
+			[] => s.is_empty(),
+			['*', '*', rest @ ..] => match rest {
+				// `**/` can also match no directories at all
+				['/', after @ ..] => {
+					(0..=s.len()).any(|i| (i == 0 || s[i - 1] == '/') && match_from(after, &s[i..]))
+				}
+				_ => (0..=s.len()).any(|i| match_from(rest, &s[i..])),
+			},
+			['*', rest @ ..] => (0..=s.len())
+				.take_while(|&i| i == 0 || s[i - 1] != '/')
+				.any(|i| match_from(rest, &s[i..])),
+			['?', rest @ ..] => !s.is_empty() && s[0] != '/' && match_from(rest, &s[1..]),
+			[c, rest @ ..] => !s.is_empty() && s[0] == *c && match_from(rest, &s[1..]),
+		}
+	}
+
+	/*
+	how specific a pattern is, for when more than one matches the same path
+	more characters that aren't wildcards is more specific, then fewer wildcards
+	*/
+	pub fn specificity(pattern: &str) -> (usize, isize) {
+		let wildcards = pattern.matches('*').count() + pattern.matches('?').count();
+		let literal = pattern.chars().filter(|&c| c != '*' && c != '?').count();
+		(literal, -(wildcards as isize))
+	}
+}
+
+// matching up the lines of some text with the lines of a changed copy of it
+pub mod lines {
+	use std::collections::HashMap;
+
+	// the most lines compared with each other before giving up on finding the longest run in common,
+	// which is 16mb of table
+	const MAX_TABLE: usize = 1 << 22;
+
+	/*
+	the line in `original` each line of `changed` came from, counting from 0,
+	or `None` for lines that were added or changed
+	lines are matched up by the longest run of lines the two have in common, in the same order,
+	or by the next line that's the same if there are too many lines for that
+	*/
+	pub fn origins(original: &str, changed: &str) -> Vec<Option<usize>> {
+		let a = original.lines().collect::<Vec<_>>();
+		let b = changed.lines().collect::<Vec<_>>();
+		// the lines at the start and end that are the same don't need comparing with everything
+		let start = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+		let end = a[start..]
+			.iter()
+			.rev()
+			.zip(b[start..].iter().rev())
+			.take_while(|(x, y)| x == y)
+			.count();
+		let (x, y) = (&a[start..a.len() - end], &b[start..b.len() - end]);
+		let middle = if x.len().saturating_mul(y.len()) > MAX_TABLE {
+			next_same(x, y)
+		} else {
+			longest(x, y)
+		};
+
+		let mut origins = (0..start).map(Some).collect::<Vec<_>>();
+		origins.extend(middle.into_iter().map(|o| o.map(|i| start + i)));
+		origins.extend((a.len() - end..a.len()).map(Some));
+		origins
+	}
+
+	// matches up lines by the longest run the two have in common, which takes `x.len() * y.len()` memory
+	fn longest(x: &[&str], y: &[&str]) -> Vec<Option<usize>> {
+		// `common[i][j]` is how many lines `x[i..]` and `y[j..]` have in common
+		let mut common = vec![vec![0u32; y.len() + 1]; x.len() + 1];
+		for i in (0..x.len()).rev() {
+			for j in (0..y.len()).rev() {
+				common[i][j] = if x[i] == y[j] {
+					common[i + 1][j + 1] + 1
+				} else {
+					common[i + 1][j].max(common[i][j + 1])
+				};
+			}
+		}
+
+		let mut origins = Vec::with_capacity(y.len());
+		let (mut i, mut j) = (0, 0);
+		while j < y.len() {
+			if i < x.len() && x[i] == y[j] {
+				origins.push(Some(i));
+				i += 1;
+				j += 1;
+			} else if i < x.len() && common[i + 1][j] >= common[i][j + 1] {
+				i += 1;
+			} else {
+				origins.push(None);
+				j += 1;
+			}
+		}
+		origins
+	}
+
+	// matches each line with the next line after the last match that's the same, which isn't always the best match
+	fn next_same(x: &[&str], y: &[&str]) -> Vec<Option<usize>> {
+		let mut lines = HashMap::<&str, Vec<usize>>::new();
+		for (i, line) in x.iter().enumerate() {
+			lines.entry(line).or_default().push(i);
+		}
+		let mut next = 0;
+		y.iter()
+			.map(|line| {
+				let found = lines.get(line).and_then(|at| {
+					let i = at.partition_point(|i| *i < next);
+					at.get(i).copied()
+				});
+				if let Some(i) = found {
+					next = i + 1;
+				}
+				found
+			})
+			.collect()
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -172,7 +478,7 @@ mod test {
 		let expect = (String::from("f"), 2);
 		assert_eq!(expect, text::first(data));
 	}
-	
+
 	#[test]
 	fn test_first_from() {
 		let data = "first  second";
@@ -185,7 +491,7 @@ mod test {
 		let data = "hello ";
 		let expect = String::from("hello");
 		assert_eq!(expect, text::remove(data, 5, 1));
-		
+
 		let data = "hello ";
 		let expect = String::from("hlo ");
 		assert_eq!(expect, text::remove(data, 1, 2));
@@ -207,4 +513,69 @@ mod test {
 		let expect = (String::from("hello world"), 2, 12);
 		assert_eq!(expect.0, text::trim(&data, 0, 0).0);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_date_parse() {
+		assert_eq!(Some(0), date::parse("1970-01-01"));
+		assert_eq!(Some(1683590400), date::parse("2023-05-09"));
+		assert_eq!(Some(1683642600), date::parse("2023-05-09 14:30"));
+		assert_eq!(Some(1683635400), date::parse("2023-05-09T14:30:00+02:00"));
+		assert_eq!(None, date::parse("09/05/2023"));
+	}
+
+	#[test]
+	fn test_date_format() {
+		let secs = 1683642600;
+		assert_eq!("2023-05-09 14:30:00", date::format(secs, "%F %T"));
+		assert_eq!(
+			"Tuesday 9 May, 02:30 PM",
+			date::format(secs, "%A %e %B, %I:%M %p")
+		);
+		assert_eq!("090523 (day 129)", date::format(secs, "%d%m%y (day %j)"));
+		assert_eq!((1969, 12, 31), date::civil(-1));
+	}
+
+	#[test]
+	fn test_glob() {
+		assert!(glob::matches("notes/**", "notes/uxn.gn"));
+		assert!(glob::matches("notes/**", "notes/a/b/c.gn"));
+		assert!(!glob::matches("notes/**", "blog/notes.gn"));
+		assert!(glob::matches("**/index.gn", "index.gn"));
+		assert!(glob::matches("**/index.gn", "a/b/index.gn"));
+		assert!(glob::matches("*.gn", "about.gn"));
+		assert!(!glob::matches("*.gn", "notes/about.gn"));
+		assert!(glob::matches("notes/?.gn", "notes/a.gn"));
+		assert!(glob::specificity("notes/*.gn") > glob::specificity("notes/**"));
+		assert!(glob::specificity("notes/**") > glob::specificity("**"));
+	}
+
+	#[test]
+	fn test_origins() {
+		let original = "!meta!\ntitle: Uxn\n!meta!\n[p| one ]\n{{list}}\n[p| two ]\n";
+		let changed = "[p| one ]\n[ul|\n[li| a ]\n]\n[p| two ]\n";
+		assert_eq!(
+			vec![Some(3), None, None, None, Some(5)],
+			lines::origins(original, changed)
+		);
+		assert_eq!(vec![Some(0), Some(1)], lines::origins("a\nb", "a\nb"));
+
+		// a long page that a plugin has changed all through is matched up without a huge table
+		let original = (0..20000)
+			.map(|i| format!("line {}\n", i))
+			.collect::<String>();
+		let changed = original
+			.lines()
+			.enumerate()
+			.map(|(i, l)| match i % 1000 {
+				500 => String::from("[p| added ]\n"),
+				_ => format!("{}\n", l),
+			})
+			.collect::<String>();
+		let origins = lines::origins(&original, &changed);
+		assert_eq!(20000, origins.len());
+		assert_eq!(Some(499), origins[499]);
+		assert_eq!(None, origins[500]);
+		assert_eq!(Some(19999), origins[19999]);
+		assert_eq!(19980, origins.iter().flatten().count());
+	}
+}